@@ -1,19 +1,24 @@
 use super::errors::{APNGError, APNGResult};
 use byteorder::{BigEndian, WriteBytesExt};
-use flate2::Compression;
 use flate2::Crc;
-use flate2::write::ZlibEncoder;
 use rayon_ordered_bridge::bounded_parralel_map;
 use rayon_ordered_bridge::bounded_parralel_map_channel;
 use std::fs::File;
 use std::io::BufWriter;
-use std::io::{self, Write};
-use std::mem;
+use std::io::{self, SeekFrom, Write};
 use std::path::PathBuf;
 use std::sync::mpsc::SyncSender;
 use std::sync::mpsc::sync_channel;
 use std::thread::JoinHandle;
+use std::time::Duration;
 
+use crate::chunk::{ChunkPosition, ChunkType};
+use crate::compress::{Compression, Deflater};
+use crate::delay::Timeline;
+use crate::delta::{DeltaMode, DeltaState, compress_smallest};
+use crate::interlace::{interlace, pass_sizes};
+use crate::metadata::{Metadata, Text};
+use crate::palette::Palette;
 use crate::png::PNGImage;
 
 #[derive(Clone, Debug, PartialEq)]
@@ -27,17 +32,56 @@ pub struct Config {
     pub color: png::ColorType,
     pub depth: png::BitDepth,
     pub filter: png::Filter,
+    // Adam7 interlace the image data of every frame
+    pub interlace: bool,
+    // how the filter of each row is chosen when filter is png::Filter::Adaptive or MinEntropy
+    pub adaptive_filter: AdaptiveFilter,
+    // zlib compression level and strategy of the image data
+    pub compression: Compression,
+    // the compressor used for the image data
+    pub deflater: Deflater,
+    // max bytes of the image data in one IDAT or fdAT chunk, None writes a frame in one chunk
+    pub max_chunk_size: Option<usize>,
+    // delay (numerator, denominator) in seconds of the frames which don't set it
+    pub default_delay: (u16, u16),
+    // ticks per second of the delays of frames written with timestamps
+    pub timescale: u16,
+    // crop frames to the region changed from the previous frame
+    pub delta: DeltaMode,
+    // required for png::ColorType::Indexed, a suggested palette for Rgb and Rgba
+    pub palette: Option<Palette>,
+    // ancillary chunks like the color space
+    pub metadata: Metadata,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum AdaptiveFilter {
+    // choose the filter with the minimum sum of absolute differences for each row
+    #[default]
+    MinSum,
+    // compress with every filter and keep the smallest output
+    BruteForce,
 }
 
 impl Config {
-    // Returns the bits per pixel
+    // Returns the bytes per pixel used by the filters, packed pixels are rounded up to 1 byte
     pub fn bytes_per_pixel(&self) -> usize {
+        self.bits_per_pixel().div_ceil(8)
+    }
+
+    // Returns the bits per pixel
+    pub fn bits_per_pixel(&self) -> usize {
         self.color.samples() * self.depth as usize
     }
 
     // Returns the number of bytes needed for one deinterlaced row
     pub fn raw_row_length(&self) -> usize {
-        let bits = self.width as usize * self.color.samples() * self.depth as usize;
+        self.raw_row_length_for(self.width)
+    }
+
+    // Returns the number of bytes needed for one deinterlaced row of the given width
+    pub fn raw_row_length_for(&self, width: u32) -> usize {
+        let bits = width as usize * self.color.samples() * self.depth as usize;
         let extra = bits % 8;
         bits / 8
             + match extra {
@@ -46,10 +90,41 @@ impl Config {
             }
             + 1 // filter method
     }
+
+    // check the color type, bit depth and palette are allowed by PNG
+    pub(crate) fn validate(&self) -> APNGResult<()> {
+        use png::BitDepth::*;
+        use png::ColorType::*;
+
+        self.validate_depth()?;
+        match (&self.palette, self.color) {
+            (None, Indexed) => Err(APNGError::PaletteNotFound),
+            (Some(_), Grayscale | GrayscaleAlpha) => Err(APNGError::InvalidPalette),
+            (Some(palette), Indexed) => palette.validate(self.depth),
+            (Some(palette), _) => palette.validate(Eight),
+            (None, _) => Ok(()),
+        }
+    }
+
+    // check the bit depth is allowed for the color type
+    pub(crate) fn validate_depth(&self) -> APNGResult<()> {
+        use png::BitDepth::*;
+        use png::ColorType::*;
+
+        let depth_ok = match self.color {
+            Grayscale => true,
+            Indexed => self.depth != Sixteen,
+            Rgb | GrayscaleAlpha | Rgba => matches!(self.depth, Eight | Sixteen),
+        };
+        if !depth_ok {
+            return Err(APNGError::InvalidBitDepth(self.color, self.depth));
+        }
+        Ok(())
+    }
 }
 
 pub struct ParallelEncoder {
-    source_tx: SyncSender<PNGImage>,
+    source_tx: SyncSender<(PNGImage, Option<Frame>)>,
     handler: JoinHandle<()>,
 }
 
@@ -63,29 +138,50 @@ impl ParallelEncoder {
         num_frames: u32,
         plays: Option<u32>,
         channel_bound: Option<usize>,
+    ) -> APNGResult<ParallelEncoder> {
+        let config = create_config_with_num_frames(&image, num_frames, plays)?;
+        Self::new_with_config(path, config, image, default_frame, channel_bound)
+    }
+
+    pub fn new_with_config(
+        path: PathBuf,
+        config: Config,
+        image: PNGImage,
+        default_frame: Option<Frame>,
+        channel_bound: Option<usize>,
     ) -> APNGResult<ParallelEncoder> {
         let (source_tx, source_rx) = sync_channel(0);
 
         let handler = std::thread::spawn(move || {
             let writer = BufWriter::new(File::create(&path).unwrap());
+            let frame = default_frame.unwrap_or_default();
+            let mut delta = DeltaState::default();
 
-            let config = create_config_with_num_frames(&image, num_frames, plays).unwrap();
-            let image_buffer = ImageBuffer::new(&config, &image).unwrap();
+            let candidates = delta.advance(&config, image, frame.clone()).unwrap();
+            let (image_buffer, first_frame) = compress_smallest(&config, candidates).unwrap();
             let mut encoder = Encoder::new(writer, config.clone()).unwrap();
             encoder
-                .write_first_frame(&image_buffer, default_frame.as_ref())
+                .write_first_frame(&image_buffer, Some(&first_frame))
                 .unwrap();
 
+            // diff against the previous frame in order, then compress in parallel
+            let source = {
+                let config = config.clone();
+                source_rx
+                    .into_iter()
+                    .map(move |(image, f): (PNGImage, Option<Frame>)| {
+                        let f = f.unwrap_or_else(|| frame.clone());
+                        delta.advance(&config, image, f).unwrap()
+                    })
+            };
             let (result, _waiter) = bounded_parralel_map(
                 channel_bound.unwrap_or(Self::DEFAULT_CHANNEL_BOUND),
-                source_rx.into_iter(),
-                move |image| ImageBuffer::new(&config, &image).unwrap(),
+                source,
+                move |candidates| compress_smallest(&config, candidates).unwrap(),
             );
 
-            for buf in result.iter() {
-                encoder
-                    .write_rest_frame(&buf, default_frame.as_ref())
-                    .unwrap();
+            for (buf, frame) in result.iter() {
+                encoder.write_rest_frame(&buf, Some(&frame)).unwrap();
             }
             encoder.finish_encode().unwrap();
         });
@@ -93,7 +189,12 @@ impl ParallelEncoder {
     }
 
     pub fn send(&self, image: PNGImage) {
-        self.source_tx.send(image).unwrap();
+        self.source_tx.send((image, None)).unwrap();
+    }
+
+    // send an image with its own frame control instead of the default frame, like a frame of Timeline
+    pub fn send_with_frame(&self, image: PNGImage, frame: Frame) {
+        self.source_tx.send((image, Some(frame))).unwrap();
     }
 
     pub fn finalize(self) {
@@ -107,18 +208,74 @@ pub struct Encoder<W: io::Write> {
     config: Config,
     w: W,
     seq_num: u32,
+    // number of frames written
+    frames: u32,
+    // IDAT is a default image which is not a part of the animation
+    default_image: bool,
+    delta: DeltaState,
+    // custom chunks waiting for their position
+    chunks: Vec<(ChunkPosition, ChunkType, Vec<u8>)>,
+    // position of acTL and the function which rewrites it, when the number of frames is unknown
+    patch_ac_tl: Option<(u64, PatchFn<W>)>,
+    // delays of the frames written with timestamps
+    timeline: Timeline,
+    // a frame written with a timestamp, waiting for the timestamp of the next frame
+    pending: Option<(PNGImage, ImageBuffer, Option<Frame>, Duration)>,
+}
+
+type PatchFn<W> = fn(&mut W, u64, &[u8]) -> io::Result<()>;
+
+impl<W: io::Write + io::Seek> Encoder<W> {
+    // Makes an encoder which doesn't need the number of frames, num_frames of the config is ignored.
+    // acTL is written with 0 frames and rewritten by finish_encode with the frames written.
+    pub fn new_with_unknown_frames(mut writer: W, config: Config) -> APNGResult<Self> {
+        let start = writer.stream_position()?;
+        let config = Config {
+            num_frames: 0,
+            ..config
+        };
+        let mut e = Self::new(writer, config)?;
+        // signature (8 bytes) and IHDR (25 bytes) come before acTL
+        e.patch_ac_tl = Some((start + 33, patch::<W>));
+        Ok(e)
+    }
+}
+
+// overwrite the bytes at pos and go back to the end
+fn patch<W: io::Write + io::Seek>(w: &mut W, pos: u64, buf: &[u8]) -> io::Result<()> {
+    let end = w.stream_position()?;
+    w.seek(SeekFrom::Start(pos))?;
+    w.write_all(buf)?;
+    w.seek(SeekFrom::Start(end))?;
+    Ok(())
 }
 
 impl<W: io::Write> Encoder<W> {
     pub fn new(writer: W, config: Config) -> APNGResult<Self> {
+        let timeline = Timeline::new(config.timescale);
         let mut e = Encoder {
             config,
             w: writer,
             seq_num: 0,
+            frames: 0,
+            default_image: false,
+            delta: DeltaState::default(),
+            chunks: vec![],
+            patch_ac_tl: None,
+            timeline,
+            pending: None,
         };
+        e.config.validate()?;
         e.write_png_header()?;
         e.write_ihdr()?;
         e.write_ac_tl()?;
+        let metadata = e.config.metadata.clone();
+        e.write_metadata(metadata.chunks_before_plte(&e.config)?)?;
+        e.write_plte()?;
+        e.write_metadata(metadata.chunks_after_plte(&e.config)?)?;
+        for text in e.config.metadata.texts.clone() {
+            e.add_text(&text)?;
+        }
         Ok(e)
     }
 
@@ -132,27 +289,58 @@ impl<W: io::Write> Encoder<W> {
     where
         F: Fn(SyncSender<(PNGImage, Option<Frame>)>),
         F: Send + 'static,
+    {
+        Self::encode_parallel_inner(
+            writer,
+            move |image| create_config_with_num_frames(image, num_frames, plays),
+            default_frame,
+            image_callback,
+        )
+    }
+
+    pub fn encode_parallel_with_config<F>(
+        writer: W,
+        config: Config,
+        default_frame: Option<Frame>,
+        image_callback: F,
+    ) -> APNGResult<()>
+    where
+        F: Fn(SyncSender<(PNGImage, Option<Frame>)>),
+        F: Send + 'static,
+    {
+        Self::encode_parallel_inner(writer, move |_| Ok(config), default_frame, image_callback)
+    }
+
+    fn encode_parallel_inner<C, F>(
+        writer: W,
+        create_config: C,
+        default_frame: Option<Frame>,
+        image_callback: F,
+    ) -> APNGResult<()>
+    where
+        C: FnOnce(&PNGImage) -> APNGResult<Config>,
+        C: Send + 'static,
+        F: Fn(SyncSender<(PNGImage, Option<Frame>)>),
+        F: Send + 'static,
     {
         let (source_tx, source_rx) = sync_channel::<(PNGImage, Option<Frame>)>(0);
         let (convert_tx, convert_rx, _waiter) = bounded_parralel_map_channel(
             32,
-            move |(png_image, frame, config): (PNGImage, Option<Frame>, Config)| {
-                (
-                    ImageBuffer::new(&config, &png_image).unwrap(),
-                    frame,
-                    config,
-                )
+            move |(candidates, config): (Vec<(PNGImage, Frame)>, Config)| {
+                let (image_buffer, frame) = compress_smallest(&config, candidates).unwrap();
+                (image_buffer, frame, config)
             },
         );
 
         rayon::spawn(move || {
             let (first_image, frame) = source_rx.recv().unwrap();
-            let config = create_config_with_num_frames(&first_image, num_frames, plays).unwrap();
-            convert_tx
-                .send((first_image, frame, config.clone()))
-                .unwrap();
-            for (image, frame) in source_rx.iter() {
-                convert_tx.send((image, frame, config.clone())).unwrap();
+            let config = create_config(&first_image).unwrap();
+            let mut delta = DeltaState::default();
+            // diff against the previous frame in order, then compress in parallel
+            for (image, frame) in std::iter::once((first_image, frame)).chain(source_rx.iter()) {
+                let frame = frame.or_else(|| default_frame.clone()).unwrap_or_default();
+                let candidates = delta.advance(&config, image, frame).unwrap();
+                convert_tx.send((candidates, config.clone())).unwrap();
             }
         });
 
@@ -162,13 +350,11 @@ impl<W: io::Write> Encoder<W> {
 
         let (image, frame, config) = convert_rx.recv().unwrap();
         let mut encoder = Self::new(writer, config).unwrap();
-        encoder
-            .write_first_frame(&image, frame.as_ref().or(default_frame.as_ref()))
-            .unwrap();
+        encoder.write_first_frame(&image, Some(&frame)).unwrap();
 
         for (image_buffer, frame, _config) in convert_rx.iter() {
             encoder
-                .write_rest_frame(&image_buffer, frame.as_ref().or(default_frame.as_ref()))
+                .write_rest_frame(&image_buffer, Some(&frame))
                 .unwrap();
         }
         encoder.finish_encode().unwrap();
@@ -177,26 +363,163 @@ impl<W: io::Write> Encoder<W> {
 
     // all png images encode to apng
     pub fn encode_all(&mut self, images: Vec<PNGImage>, frame: Option<&Frame>) -> APNGResult<()> {
-        for (i, v) in images.iter().enumerate() {
-            let image_buffer = ImageBuffer::new(&self.config, v)?;
-            if i == 0 {
-                self.write_first_frame(&image_buffer, frame)?;
-            } else {
-                self.write_rest_frame(&image_buffer, frame)?;
-            }
+        for v in images.iter() {
+            let (image_buffer, frame) = self.prepare_frame(v, frame)?;
+            self.write_image_buffer(v, &image_buffer, frame.as_ref())?;
+        }
+        self.finish_encode()
+    }
+
+    // Writes the image shown by viewers which don't support APNG as IDAT without fcTL.
+    // The animation starts from the next frame and num_frames of the config doesn't count it.
+    pub fn write_default_image(&mut self, image: &PNGImage) -> APNGResult<()> {
+        if self.frames > 0 || self.default_image || self.pending.is_some() {
+            return Err(APNGError::DefaultImageAfterFrames);
+        }
+        if image.width != self.config.width || image.height != self.config.height {
+            return Err(APNGError::WrongFrameSize(
+                self.config.width,
+                self.config.height,
+                image.width,
+                image.height,
+            ));
+        }
+        let image_buffer = ImageBuffer::new(&self.config, image)?;
+        self.write_custom_chunks(ChunkPosition::BeforeImageData)?;
+        self.write_idats(&image_buffer)?;
+        self.write_custom_chunks(ChunkPosition::AfterImageData)?;
+        self.default_image = true;
+        Ok(())
+    }
+
+    // write tEXt, zTXt or iTXt, it can be placed before or after any frame
+    pub fn add_text(&mut self, text: &Text) -> APNGResult<()> {
+        let (c_type, c_data) = text.chunk(&self.config)?;
+        self.write_chunk(&c_data, c_type)
+    }
+
+    // Adds an ancillary chunk which is not known to the encoder, like private data of the frames.
+    // It is written when the encoder reaches the position.
+    pub fn add_chunk(
+        &mut self,
+        chunk_type: ChunkType,
+        data: Vec<u8>,
+        position: ChunkPosition,
+    ) -> APNGResult<()> {
+        chunk_type.validate()?;
+        // IDAT is written and something is written after it
+        let (idat, after_idat) = match self.default_image {
+            true => (true, self.frames > 0),
+            false => (self.frames > 0, self.frames > 1),
+        };
+        let passed = match position {
+            ChunkPosition::BeforeImageData => idat,
+            ChunkPosition::AfterImageData => after_idat,
+            ChunkPosition::Frame(i) => self.frames > i,
+        };
+        if passed {
+            return Err(APNGError::InvalidChunkPosition);
+        }
+        if position == ChunkPosition::AfterImageData && idat {
+            return self.write_chunk(&data, chunk_type.0);
+        }
+        self.chunks.push((position, chunk_type, data));
+        Ok(())
+    }
+
+    // write the custom chunks of the position in the order they are added
+    fn write_custom_chunks(&mut self, position: ChunkPosition) -> APNGResult<()> {
+        let (chunks, rest) = std::mem::take(&mut self.chunks)
+            .into_iter()
+            .partition(|(p, _, _)| *p == position);
+        self.chunks = rest;
+        for (_, chunk_type, data) in chunks {
+            self.write_chunk(&data, chunk_type.0)?;
         }
-        self.write_iend()?;
         Ok(())
     }
 
     // write each frame control
     pub fn write_frame(&mut self, image: &PNGImage, frame: Frame) -> APNGResult<()> {
-        let image_buffer = ImageBuffer::new(&self.config, image)?;
-        if self.seq_num == 0 {
-            self.write_first_frame(&image_buffer, Some(&frame))
+        // the delay of the pending frame needs the timestamp of this frame
+        if self.pending.is_some() {
+            return Err(APNGError::TimestampNotFound);
+        }
+        let (image_buffer, frame) = self.prepare_frame(image, Some(&frame))?;
+        self.write_image_buffer(image, &image_buffer, frame.as_ref())
+    }
+
+    // Writes a frame shown from the presentation timestamp, the delay of the frame is set by the next
+    // timestamp, so the frame is written with the next frame or by finish_encode_at.
+    pub fn write_frame_at(
+        &mut self,
+        image: &PNGImage,
+        frame: Frame,
+        pts: Duration,
+    ) -> APNGResult<()> {
+        self.write_pending(Some(pts))?;
+        let (image_buffer, frame) = self.prepare_frame(image, Some(&frame))?;
+        self.pending = Some((image.clone(), image_buffer, frame, pts));
+        Ok(())
+    }
+
+    // the end is the time when the last frame written with a timestamp is hidden
+    pub fn finish_encode_at(&mut self, end: Duration) -> APNGResult<()> {
+        self.write_pending(Some(end))?;
+        self.finish_encode()
+    }
+
+    // the pending frame keeps its own delay without the next timestamp
+    fn write_pending(&mut self, next_pts: Option<Duration>) -> APNGResult<()> {
+        let Some((image, image_buffer, frame, pts)) = self.pending.take() else {
+            return Ok(());
+        };
+        let frame = match next_pts {
+            Some(next_pts) => {
+                let delay = self.timeline.frame(pts, next_pts);
+                Some(Frame {
+                    delay_num: delay.delay_num,
+                    delay_den: delay.delay_den,
+                    ..frame.unwrap_or_default()
+                })
+            }
+            None => frame,
+        };
+        self.write_image_buffer(&image, &image_buffer, frame.as_ref())
+    }
+
+    // The first frame is IDAT unless the default image is written.
+    // The next frame is diffed against the image only after it is written.
+    fn write_image_buffer(
+        &mut self,
+        image: &PNGImage,
+        image_buffer: &ImageBuffer,
+        frame: Option<&Frame>,
+    ) -> APNGResult<()> {
+        if self.frames == 0 && !self.default_image {
+            self.write_first_frame(image_buffer, frame)?;
         } else {
-            self.write_rest_frame(&image_buffer, Some(&frame))
+            self.write_rest_frame(image_buffer, frame)?;
+        }
+        self.delta.accept(&self.config, image);
+        Ok(())
+    }
+
+    // compress the image, cropped to the changed region when delta is enabled
+    fn prepare_frame(
+        &mut self,
+        image: &PNGImage,
+        frame: Option<&Frame>,
+    ) -> APNGResult<(ImageBuffer, Option<Frame>)> {
+        // checked before the image is compressed, an empty image can't be split into rows
+        self.check_region(image.width, image.height, frame)?;
+        if self.config.delta == DeltaMode::Off {
+            return Ok((ImageBuffer::new(&self.config, image)?, frame.cloned()));
         }
+        let frame = frame.cloned().unwrap_or_default();
+        let candidates = self.delta.candidates(&self.config, image.clone(), frame)?;
+        let (image_buffer, frame) = compress_smallest(&self.config, candidates)?;
+        Ok((image_buffer, Some(frame)))
     }
 
     fn write_first_frame(
@@ -204,8 +527,15 @@ impl<W: io::Write> Encoder<W> {
         image_buffer: &ImageBuffer,
         frame: Option<&Frame>,
     ) -> APNGResult<()> {
-        self.write_fc_tl(frame)?;
-        self.write_idats(image_buffer)
+        self.check_region(image_buffer.width, image_buffer.height, frame)?;
+        self.check_frame_count()?;
+        self.write_custom_chunks(ChunkPosition::BeforeImageData)?;
+        self.write_fc_tl(image_buffer, frame)?;
+        self.write_custom_chunks(ChunkPosition::Frame(0))?;
+        self.write_idats(image_buffer)?;
+        self.write_custom_chunks(ChunkPosition::AfterImageData)?;
+        self.frames += 1;
+        Ok(())
     }
 
     fn write_rest_frame(
@@ -213,23 +543,109 @@ impl<W: io::Write> Encoder<W> {
         image_buffer: &ImageBuffer,
         frame: Option<&Frame>,
     ) -> APNGResult<()> {
-        self.write_fc_tl(frame)?;
-        self.write_fd_at(image_buffer)
+        self.check_region(image_buffer.width, image_buffer.height, frame)?;
+        self.check_frame_count()?;
+        self.write_fc_tl(image_buffer, frame)?;
+        self.write_custom_chunks(ChunkPosition::Frame(self.frames))?;
+        self.write_fd_at(image_buffer)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    // a frame more than num_frames makes the file invalid
+    fn check_frame_count(&self) -> APNGResult<()> {
+        if self.patch_ac_tl.is_none() && self.frames >= self.config.num_frames {
+            return Err(APNGError::WrongFrameNums(
+                self.config.num_frames as usize,
+                self.frames as usize + 1,
+            ));
+        }
+        Ok(())
+    }
+
+    // Returns the frame region (width, height, offset_x, offset_y) after checking it fits in the canvas
+    fn frame_region(
+        &self,
+        image_width: u32,
+        image_height: u32,
+        frame: Option<&Frame>,
+    ) -> APNGResult<(u32, u32, u32, u32)> {
+        let width = frame.and_then(|f| f.width).unwrap_or(image_width);
+        let height = frame.and_then(|f| f.height).unwrap_or(image_height);
+        if width != image_width || height != image_height {
+            return Err(APNGError::WrongFrameSize(
+                width,
+                height,
+                image_width,
+                image_height,
+            ));
+        }
+
+        let offset_x = frame.and_then(|f| f.offset_x).unwrap_or(0);
+        let offset_y = frame.and_then(|f| f.offset_y).unwrap_or(0);
+        if width == 0
+            || height == 0
+            || offset_x as u64 + width as u64 > self.config.width as u64
+            || offset_y as u64 + height as u64 > self.config.height as u64
+        {
+            return Err(APNGError::FrameOutOfBounds);
+        }
+        Ok((width, height, offset_x, offset_y))
+    }
+
+    // the region of the next frame must be in the canvas
+    fn check_region(
+        &self,
+        image_width: u32,
+        image_height: u32,
+        frame: Option<&Frame>,
+    ) -> APNGResult<()> {
+        let (width, height, offset_x, offset_y) =
+            self.frame_region(image_width, image_height, frame)?;
+        // the default image is the first frame, so it must cover the whole canvas
+        if self.frames == 0
+            && !self.default_image
+            && (width != self.config.width
+                || height != self.config.height
+                || offset_x != 0
+                || offset_y != 0)
+        {
+            return Err(APNGError::InvalidFirstFrame);
+        }
+        Ok(())
     }
 
     // finish encode, write end chunk on the last line.
     pub fn finish_encode(&mut self) -> APNGResult<()> {
-        let encoded_frames = self.seq_num + 1;
-        if self.config.num_frames > encoded_frames {
+        self.write_pending(None)?;
+        if let Some((pos, patch)) = self.patch_ac_tl {
+            if self.frames == 0 {
+                return Err(APNGError::ImagesNotFound);
+            }
+            self.config.num_frames = self.frames;
+            let mut buf = vec![];
+            write_chunk(&mut buf, &self.ac_tl_data()?, *b"acTL")?;
+            patch(&mut self.w, pos, &buf)?;
+        }
+        if self.config.num_frames != self.frames {
             return Err(APNGError::WrongFrameNums(
                 self.config.num_frames as usize,
-                encoded_frames as usize,
+                self.frames as usize,
             ));
         }
+        // a custom chunk of a frame which is not written
+        if !self.chunks.is_empty() {
+            return Err(APNGError::InvalidChunkPosition);
+        }
 
         self.write_iend()
     }
 
+    // the writer, after finish_encode
+    pub(crate) fn into_writer(self) -> W {
+        self.w
+    }
+
     fn write_png_header(&mut self) -> APNGResult<()> {
         self.w.write_all(b"\x89PNG\r\n\x1a\n")?;
         Ok(())
@@ -243,26 +659,66 @@ impl<W: io::Write> Encoder<W> {
         let mut buf = vec![];
         buf.write_u32::<BigEndian>(self.config.width)?;
         buf.write_u32::<BigEndian>(self.config.height)?;
-        buf.write_all(&[self.config.depth as u8, self.config.color as u8, 0, 0, 0])?;
+        buf.write_all(&[
+            self.config.depth as u8,
+            self.config.color as u8,
+            0,
+            0,
+            u8::from(self.config.interlace),
+        ])?;
         self.write_chunk(&buf, *b"IHDR")
     }
 
     fn write_ac_tl(&mut self) -> APNGResult<()> {
+        let buf = self.ac_tl_data()?;
+        self.write_chunk(&buf, *b"acTL")
+    }
+
+    fn ac_tl_data(&self) -> APNGResult<Vec<u8>> {
         let mut buf = vec![];
         buf.write_u32::<BigEndian>(self.config.num_frames)?;
         buf.write_u32::<BigEndian>(self.config.num_plays)?;
-        self.write_chunk(&buf, *b"acTL")
+        Ok(buf)
     }
 
-    fn write_fc_tl(&mut self, frame: Option<&Frame>) -> APNGResult<()> {
+    fn write_metadata(&mut self, chunks: Vec<([u8; 4], Vec<u8>)>) -> APNGResult<()> {
+        for (c_type, c_data) in chunks {
+            self.write_chunk(&c_data, c_type)?;
+        }
+        Ok(())
+    }
+
+    // the palette is written once before the first image data
+    fn write_plte(&mut self) -> APNGResult<()> {
+        let palette = match self.config.palette.clone() {
+            Some(palette) => palette,
+            None => return Ok(()),
+        };
+        self.write_chunk(&palette.plte(), *b"PLTE")?;
+        // tRNS of truecolor images is a transparent color, not the alpha of the palette
+        if self.config.color == png::ColorType::Indexed && !palette.alpha.is_empty() {
+            self.write_chunk(&palette.alpha, *b"tRNS")?;
+        }
+        Ok(())
+    }
+
+    fn write_fc_tl(&mut self, image_buffer: &ImageBuffer, frame: Option<&Frame>) -> APNGResult<()> {
         let mut buf = vec![];
         buf.write_u32::<BigEndian>(self.seq_num)?;
-        buf.write_u32::<BigEndian>(frame.and_then(|f| f.width).unwrap_or(self.config.width))?;
-        buf.write_u32::<BigEndian>(frame.and_then(|f| f.height).unwrap_or(self.config.height))?;
+        buf.write_u32::<BigEndian>(frame.and_then(|f| f.width).unwrap_or(image_buffer.width))?;
+        buf.write_u32::<BigEndian>(frame.and_then(|f| f.height).unwrap_or(image_buffer.height))?;
         buf.write_u32::<BigEndian>(frame.and_then(|f| f.offset_x).unwrap_or(0))?;
         buf.write_u32::<BigEndian>(frame.and_then(|f| f.offset_y).unwrap_or(0))?;
-        buf.write_u16::<BigEndian>(frame.and_then(|f| f.delay_num).unwrap_or(1))?;
-        buf.write_u16::<BigEndian>(frame.and_then(|f| f.delay_den).unwrap_or(3))?;
+        buf.write_u16::<BigEndian>(
+            frame
+                .and_then(|f| f.delay_num)
+                .unwrap_or(self.config.default_delay.0),
+        )?;
+        buf.write_u16::<BigEndian>(
+            frame
+                .and_then(|f| f.delay_den)
+                .unwrap_or(self.config.default_delay.1),
+        )?;
 
         let dis = frame
             .and_then(|f| f.dispose_op)
@@ -279,35 +735,49 @@ impl<W: io::Write> Encoder<W> {
     }
 
     fn write_fd_at(&mut self, data: &ImageBuffer) -> APNGResult<()> {
-        let mut buf = vec![];
-        buf.write_u32::<BigEndian>(self.seq_num)?;
-        buf.write_all(&data.0)?;
-        self.write_chunk(&buf, *b"fdAT")?;
-        self.seq_num += 1;
+        for c_data in data.data.chunks(self.chunk_size(data)) {
+            let mut buf = vec![];
+            buf.write_u32::<BigEndian>(self.seq_num)?;
+            buf.write_all(c_data)?;
+            self.write_chunk(&buf, *b"fdAT")?;
+            self.seq_num += 1;
+        }
         Ok(())
     }
 
     // Writes the image data.
     fn write_idats(&mut self, data: &ImageBuffer) -> APNGResult<()> {
-        self.write_chunk(&data.0, *b"IDAT")
+        for c_data in data.data.chunks(self.chunk_size(data)) {
+            self.write_chunk(c_data, *b"IDAT")?;
+        }
+        Ok(())
+    }
+
+    // Returns the size to split the image data into
+    fn chunk_size(&self, data: &ImageBuffer) -> usize {
+        self.config.max_chunk_size.unwrap_or(data.data.len()).max(1)
     }
 
     // write chunk data 4 field
     fn write_chunk(&mut self, c_data: &[u8], c_type: [u8; 4]) -> APNGResult<()> {
-        // Header(Length and Type)
-        self.w.write_u32::<BigEndian>(c_data.len() as u32)?;
-        self.w.write_all(&c_type)?;
-        // Data
-        self.w.write_all(c_data)?;
-        // Footer (CRC)
-        let mut crc = Crc::new();
-        crc.update(&c_type);
-        crc.update(c_data);
-        self.w.write_u32::<BigEndian>(crc.sum())?;
-        Ok(())
+        write_chunk(&mut self.w, c_data, c_type)
     }
 }
 
+fn write_chunk<W: io::Write>(w: &mut W, c_data: &[u8], c_type: [u8; 4]) -> APNGResult<()> {
+    // Header(Length and Type)
+    w.write_u32::<BigEndian>(c_data.len() as u32)?;
+    w.write_all(&c_type)?;
+    // Data
+    w.write_all(c_data)?;
+    // Footer (CRC)
+    let mut crc = Crc::new();
+    crc.update(&c_type);
+    crc.update(c_data);
+    w.write_u32::<BigEndian>(crc.sum())?;
+    Ok(())
+}
+
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub struct Frame {
     pub width: Option<u32>,
@@ -346,6 +816,16 @@ pub fn create_config(images: &[PNGImage], plays: Option<u32>) -> APNGResult<Conf
         color: default_image.color_type,
         depth: default_image.bit_depth,
         filter: png::Filter::NoFilter, //default
+        adaptive_filter: AdaptiveFilter::MinSum,
+        compression: Compression::best(),
+        deflater: Deflater::Miniz,
+        max_chunk_size: None,
+        default_delay: (1, 3),
+        timescale: 1000,
+        delta: DeltaMode::Off,
+        palette: None,
+        interlace: false,
+        metadata: Metadata::default(),
     })
 }
 
@@ -362,10 +842,20 @@ pub fn create_config_with_num_frames(
         color: image.color_type,
         depth: image.bit_depth,
         filter: png::Filter::NoFilter, //default
+        adaptive_filter: AdaptiveFilter::MinSum,
+        compression: Compression::best(),
+        deflater: Deflater::Miniz,
+        max_chunk_size: None,
+        default_delay: (1, 3),
+        timescale: 1000,
+        delta: DeltaMode::Off,
+        palette: None,
+        interlace: false,
+        metadata: Metadata::default(),
     })
 }
 
-fn filter_path(a: u8, b: u8, c: u8) -> u8 {
+pub(crate) fn filter_path(a: u8, b: u8, c: u8) -> u8 {
     let ia = i16::from(a);
     let ib = i16::from(b);
     let ic = i16::from(c);
@@ -385,7 +875,9 @@ fn filter_path(a: u8, b: u8, c: u8) -> u8 {
     }
 }
 
-pub fn filter(method: png::Filter, bpp: usize, previous: &[u8], current: &mut [u8]) {
+// Applies the filter to the row and returns the filter actually used,
+// which is chosen per row by the minimum sum of absolute differences for Adaptive.
+pub fn filter(method: png::Filter, bpp: usize, previous: &[u8], current: &mut [u8]) -> png::Filter {
     use png::Filter::*;
     assert!(bpp > 0);
     let len = current.len();
@@ -404,8 +896,8 @@ pub fn filter(method: png::Filter, bpp: usize, previous: &[u8], current: &mut [u
         }
         Avg => {
             for i in (bpp..len).rev() {
-                current[i] =
-                    current[i].wrapping_sub(current[i - bpp].wrapping_add(previous[i]) / 2);
+                let avg = (u16::from(current[i - bpp]) + u16::from(previous[i])) / 2;
+                current[i] = current[i].wrapping_sub(avg as u8);
             }
 
             for i in 0..bpp {
@@ -425,43 +917,148 @@ pub fn filter(method: png::Filter, bpp: usize, previous: &[u8], current: &mut [u
                 current[i] = current[i].wrapping_sub(filter_path(0, previous[i], 0));
             }
         }
-        Adaptive => {
-            // This is not actually used in PNG encoding.
-            panic!("Adaptive filter is not supported for encoding.");
+        // MinEntropy is chosen by the sum like Adaptive
+        _ => {
+            let mut best: Option<(u64, png::Filter, Vec<u8>)> = None;
+            for method in [NoFilter, Sub, Up, Avg, Paeth] {
+                let mut row = current.to_vec();
+                filter(method, bpp, previous, &mut row);
+                // the filtered bytes are treated as signed values
+                let sum = row
+                    .iter()
+                    .map(|&v| u64::from((v as i8).unsigned_abs()))
+                    .sum();
+                if best.as_ref().is_none_or(|(s, _, _)| sum < *s) {
+                    best = Some((sum, method, row));
+                }
+            }
+            let (_, method, row) = best.unwrap();
+            current.copy_from_slice(&row);
+            return method;
         }
-        _ => todo!(),
     }
+    method
 }
 
-struct ImageBuffer(Vec<u8>);
+// every index must point to an entry of the palette, the padding bits at the row end are skipped
+fn check_indices(
+    palette: &Palette,
+    depth: usize,
+    width: usize,
+    row_len: usize,
+    data: &[u8],
+) -> APNGResult<()> {
+    let mask = ((1u16 << depth) - 1) as u8;
+    for row in data.chunks(row_len) {
+        for x in 0..width {
+            let bit = x * depth;
+            let index = usize::from((row[bit / 8] >> (8 - depth - bit % 8)) & mask);
+            if index >= palette.len() {
+                return Err(APNGError::PaletteIndexOutOfRange(index));
+            }
+        }
+    }
+    Ok(())
+}
+
+// compressed image data of a frame, its size may be smaller than the canvas
+#[derive(Debug, PartialEq)]
+pub(crate) struct ImageBuffer {
+    pub(crate) data: Vec<u8>,
+    width: u32,
+    height: u32,
+}
 
 impl ImageBuffer {
-    fn new(config: &Config, png_image: &PNGImage) -> APNGResult<ImageBuffer> {
+    pub(crate) fn new(config: &Config, png_image: &PNGImage) -> APNGResult<ImageBuffer> {
         let data = &png_image.data;
-        let mut buf = Vec::new();
-        let bpp = config.bytes_per_pixel();
-        let in_len = config.raw_row_length() - 1;
-
-        let mut prev = vec![0; in_len];
-        let mut current = vec![0; in_len];
+        let in_len = config.raw_row_length_for(png_image.width) - 1;
 
-        let data_size = in_len * config.height as usize;
+        let data_size = in_len * png_image.height as usize;
         if data_size != data.len() {
             return Err(APNGError::WrongDataSize(data_size, data.len()));
         }
+        if let Some(palette) = &config.palette
+            && config.color == png::ColorType::Indexed
+        {
+            let width = png_image.width as usize;
+            check_indices(palette, config.depth as usize, width, in_len, data)?;
+        }
+
+        let buf = match (config.filter, config.adaptive_filter) {
+            (png::Filter::Adaptive | png::Filter::MinEntropy, AdaptiveFilter::BruteForce) => {
+                let mut smallest: Option<Vec<u8>> = None;
+                for method in [
+                    png::Filter::NoFilter,
+                    png::Filter::Sub,
+                    png::Filter::Up,
+                    png::Filter::Avg,
+                    png::Filter::Paeth,
+                    png::Filter::Adaptive,
+                ] {
+                    let buf = Self::compress(config, method, png_image)?;
+                    if smallest.as_ref().is_none_or(|s| buf.len() < s.len()) {
+                        smallest = Some(buf);
+                    }
+                }
+                smallest.unwrap()
+            }
+            (method, _) => Self::compress(config, method, png_image)?,
+        };
+
+        Ok(ImageBuffer {
+            data: buf,
+            width: png_image.width,
+            height: png_image.height,
+        })
+    }
 
-        let mut zlib = ZlibEncoder::new(&mut buf, Compression::best());
-        let filter_method = config.filter;
+    // filter each row and compress them, each Adam7 pass is filtered as a separate image
+    fn compress(
+        config: &Config,
+        filter_method: png::Filter,
+        png_image: &PNGImage,
+    ) -> APNGResult<Vec<u8>> {
+        let (width, height) = (png_image.width as usize, png_image.height as usize);
+        let data = &png_image.data;
+        let mut buf = Vec::with_capacity(data.len() + height * 2);
+
+        if config.interlace {
+            let passes = interlace(config.bits_per_pixel(), width, height, data);
+            for ((w, _), pass) in pass_sizes(width, height).into_iter().zip(passes) {
+                // empty passes have no rows
+                if !pass.is_empty() {
+                    let in_len = config.raw_row_length_for(w as u32) - 1;
+                    Self::filter_rows(config, filter_method, in_len, &pass, &mut buf);
+                }
+            }
+        } else {
+            let in_len = config.raw_row_length_for(png_image.width) - 1;
+            Self::filter_rows(config, filter_method, in_len, data, &mut buf);
+        }
+
+        config.deflater.deflate(&buf, config.compression)
+    }
+
+    fn filter_rows(
+        config: &Config,
+        filter_method: png::Filter,
+        in_len: usize,
+        data: &[u8],
+        buf: &mut Vec<u8>,
+    ) {
+        let bpp = config.bytes_per_pixel();
+
+        let mut prev = vec![0; in_len];
+        let mut current = vec![0; in_len];
 
         for line in data.chunks(in_len) {
             current.copy_from_slice(line);
-            zlib.write_all(&[filter_method as u8])?;
-            filter(filter_method, bpp, &prev, &mut current);
-            zlib.write_all(&current)?;
-            mem::swap(&mut prev, &mut current);
+            let applied = filter(filter_method, bpp, &prev, &mut current);
+            buf.push(applied as u8);
+            buf.extend_from_slice(&current);
+            // the next row is filtered against the unfiltered row
+            prev.copy_from_slice(line);
         }
-
-        zlib.finish()?;
-        Ok(ImageBuffer(buf))
     }
 }
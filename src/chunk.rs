@@ -0,0 +1,58 @@
+use super::errors::{APNGError, APNGResult};
+
+// chunks written by the encoder itself, they can't be added as custom chunks
+const KNOWN_CHUNKS: [&[u8; 4]; 19] = [
+    b"IHDR", b"PLTE", b"IDAT", b"IEND", b"acTL", b"fcTL", b"fdAT", b"tRNS", b"cHRM", b"gAMA",
+    b"iCCP", b"sBIT", b"sRGB", b"bKGD", b"pHYs", b"tIME", b"tEXt", b"zTXt", b"iTXt",
+];
+
+// 4 letters of a chunk type, the case of each letter is a property bit
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ChunkType(pub [u8; 4]);
+
+impl ChunkType {
+    // lowercase 1st letter, decoders may skip the chunk
+    pub fn is_ancillary(&self) -> bool {
+        self.0[0] & 0x20 != 0
+    }
+
+    // lowercase 2nd letter, not defined by the PNG specification
+    pub fn is_private(&self) -> bool {
+        self.0[1] & 0x20 != 0
+    }
+
+    // the 3rd letter must be uppercase
+    pub fn is_reserved_bit_valid(&self) -> bool {
+        self.0[2] & 0x20 == 0
+    }
+
+    // lowercase 4th letter, editors may copy the chunk without knowing it
+    pub fn is_safe_to_copy(&self) -> bool {
+        self.0[3] & 0x20 != 0
+    }
+
+    // Custom chunks must be ancillary, so that decoders which don't know them can skip them.
+    pub(crate) fn validate(&self) -> APNGResult<()> {
+        if !self.0.iter().all(u8::is_ascii_alphabetic)
+            || !self.is_ancillary()
+            || !self.is_reserved_bit_valid()
+            || KNOWN_CHUNKS.contains(&&self.0)
+        {
+            return Err(APNGError::InvalidChunk(
+                String::from_utf8_lossy(&self.0).into_owned(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// where a custom chunk is written
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChunkPosition {
+    // before IDAT, and the frame control of the first frame
+    BeforeImageData,
+    // right after IDAT, the first frame or the default image
+    AfterImageData,
+    // between the frame control and the image data of the frame, 0 is the first frame
+    Frame(u32),
+}
@@ -0,0 +1,125 @@
+use super::errors::APNGResult;
+use miniz_oxide::deflate::core::{
+    CompressorOxide, TDEFLFlush, TDEFLStatus, compress_to_output, create_comp_flags_from_zip_params,
+};
+use std::io;
+#[cfg(feature = "zopfli")]
+use std::num::NonZeroU64;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum CompressionStrategy {
+    #[default]
+    Default = 0,
+    // only use matches that are at least 5 bytes long
+    Filtered = 1,
+    // don't look for matches, only huffman encode the literals
+    HuffmanOnly = 2,
+    // only look for matches with a distance of 1
+    Rle = 3,
+    // only use the fixed huffman codes
+    Fixed = 4,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Compression {
+    // 0 (no compression) to 9 (best) like zlib, 10 is slower and a bit smaller
+    pub level: u8,
+    pub strategy: CompressionStrategy,
+}
+
+impl Compression {
+    pub const fn fast() -> Self {
+        Compression {
+            level: 1,
+            strategy: CompressionStrategy::Default,
+        }
+    }
+
+    pub const fn best() -> Self {
+        Compression {
+            level: 9,
+            strategy: CompressionStrategy::Default,
+        }
+    }
+
+    pub const fn rle() -> Self {
+        Compression {
+            level: 6,
+            strategy: CompressionStrategy::Rle,
+        }
+    }
+
+    pub const fn huffman_only() -> Self {
+        Compression {
+            level: 6,
+            strategy: CompressionStrategy::HuffmanOnly,
+        }
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression {
+            level: 6,
+            strategy: CompressionStrategy::Default,
+        }
+    }
+}
+
+// the implementation which makes the zlib stream of the image data
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Deflater {
+    // miniz_oxide with the level and strategy of Compression
+    #[default]
+    Miniz,
+    // exhaustive search of zopfli, much slower but smaller.
+    // The level and strategy of Compression are not used.
+    #[cfg(feature = "zopfli")]
+    Zopfli { iterations: NonZeroU64 },
+}
+
+impl Deflater {
+    #[cfg(feature = "zopfli")]
+    pub fn zopfli() -> Self {
+        Deflater::Zopfli {
+            iterations: zopfli::Options::default().iteration_count,
+        }
+    }
+
+    // compress the data into a zlib stream
+    pub(crate) fn deflate(&self, data: &[u8], compression: Compression) -> APNGResult<Vec<u8>> {
+        match self {
+            Deflater::Miniz => compress_miniz(data, compression),
+            #[cfg(feature = "zopfli")]
+            Deflater::Zopfli { iterations } => compress_zopfli(data, *iterations),
+        }
+    }
+}
+
+fn compress_miniz(data: &[u8], compression: Compression) -> APNGResult<Vec<u8>> {
+    // positive window bits make a zlib header
+    let flags =
+        create_comp_flags_from_zip_params(compression.level.into(), 1, compression.strategy as i32);
+    let mut compressor = CompressorOxide::new(flags);
+
+    let mut buf = Vec::with_capacity(data.len() / 2);
+    let (status, _) = compress_to_output(&mut compressor, data, TDEFLFlush::Finish, |out| {
+        buf.extend_from_slice(out);
+        true
+    });
+    match status {
+        TDEFLStatus::Done => Ok(buf),
+        _ => Err(io::Error::other("failed to compress the image data").into()),
+    }
+}
+
+#[cfg(feature = "zopfli")]
+fn compress_zopfli(data: &[u8], iterations: NonZeroU64) -> APNGResult<Vec<u8>> {
+    let options = zopfli::Options {
+        iteration_count: iterations,
+        ..Default::default()
+    };
+    let mut buf = Vec::with_capacity(data.len() / 2);
+    zopfli::compress(options, zopfli::Format::Zlib, data, &mut buf)?;
+    Ok(buf)
+}
@@ -0,0 +1,434 @@
+use super::errors::{APNGError, APNGResult};
+use byteorder::{BigEndian, ReadBytesExt};
+use flate2::Crc;
+use flate2::read::ZlibDecoder;
+use std::io::{self, Read};
+
+use crate::apng::{AdaptiveFilter, BlendOp, Config, DisposeOp, Frame, filter_path};
+use crate::compress::{Compression, Deflater};
+use crate::delta::DeltaMode;
+use crate::interlace::{deinterlace, pass_sizes};
+use crate::metadata::Metadata;
+use crate::palette::Palette;
+use crate::png::PNGImage;
+
+struct Chunk {
+    c_type: [u8; 4],
+    data: Vec<u8>,
+}
+
+pub struct Decoder<R: io::Read> {
+    config: Config,
+    r: R,
+    seq_num: u32,
+    // acTL was found before the image data
+    animated: bool,
+    // chunk read ahead which belongs to the next frame
+    pending: Option<Chunk>,
+    // IDAT without fcTL, which is not a part of the animation
+    default_image: Option<PNGImage>,
+    finished: bool,
+}
+
+impl<R: io::Read> Decoder<R> {
+    // read the header chunks until the first frame control or image data
+    pub fn new(reader: R) -> APNGResult<Self> {
+        let mut d = Decoder {
+            config: Config {
+                width: 0,
+                height: 0,
+                num_frames: 1,
+                num_plays: 0,
+                color: png::ColorType::Rgba,
+                depth: png::BitDepth::Eight,
+                filter: png::Filter::NoFilter,
+                adaptive_filter: AdaptiveFilter::MinSum,
+                compression: Compression::best(),
+                deflater: Deflater::Miniz,
+                max_chunk_size: None,
+                default_delay: (1, 3),
+                timescale: 1000,
+                delta: DeltaMode::Off,
+                palette: None,
+                interlace: false,
+                metadata: Metadata::default(),
+            },
+            r: reader,
+            seq_num: 0,
+            animated: false,
+            pending: None,
+            default_image: None,
+            finished: false,
+        };
+        d.read_png_header()?;
+
+        let ihdr = d.read_chunk()?;
+        if &ihdr.c_type != b"IHDR" {
+            return Err(APNGError::InvalidChunk("IHDR".to_string()));
+        }
+        d.read_ihdr(&ihdr.data)?;
+
+        loop {
+            let chunk = d.read_chunk()?;
+            match &chunk.c_type {
+                b"acTL" => d.read_ac_tl(&chunk.data)?,
+                b"PLTE" => d.read_plte(&chunk.data)?,
+                b"tRNS" => d.read_t_rns(&chunk.data)?,
+                b"fcTL" | b"IDAT" => {
+                    d.pending = Some(chunk);
+                    break;
+                }
+                b"IEND" => return Err(APNGError::ImagesNotFound),
+                c_type => d.read_metadata(c_type, &chunk.data)?,
+            }
+        }
+        // the color type, bit depth and palette must be allowed by PNG
+        d.config.validate()?;
+        if d.animated && d.pending.as_ref().is_some_and(|c| &c.c_type == b"IDAT") {
+            d.read_default_image()?;
+        }
+        Ok(d)
+    }
+
+    // Returns the image of IDAT when it is not the first frame of the animation
+    pub fn default_image(&self) -> Option<&PNGImage> {
+        self.default_image.as_ref()
+    }
+
+    fn read_default_image(&mut self) -> APNGResult<()> {
+        let mut data = vec![];
+        loop {
+            let chunk = match self.pending.take() {
+                Some(chunk) => chunk,
+                None => self.read_chunk()?,
+            };
+            if &chunk.c_type != b"IDAT" {
+                self.pending = Some(chunk);
+                break;
+            }
+            data.extend_from_slice(&chunk.data);
+        }
+        let (width, height) = (self.config.width, self.config.height);
+        self.default_image = Some(self.decode_image(width, height, &data)?);
+        Ok(())
+    }
+
+    // returns the config reconstructed from IHDR, acTL, PLTE and the metadata chunks
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    // decode the next frame, returns None after the last frame
+    pub fn next_frame(&mut self) -> APNGResult<Option<(PNGImage, Frame)>> {
+        let mut frame: Option<Frame> = None;
+        let mut data = vec![];
+
+        while !self.finished {
+            let chunk = match self.pending.take() {
+                Some(chunk) => chunk,
+                None => self.read_chunk()?,
+            };
+            match &chunk.c_type {
+                b"fcTL" => {
+                    if frame.is_some() {
+                        self.pending = Some(chunk);
+                        break;
+                    }
+                    frame = Some(self.read_fc_tl(&chunk.data)?);
+                }
+                b"IDAT" => {
+                    if !self.animated && frame.is_none() {
+                        frame = Some(Frame {
+                            width: Some(self.config.width),
+                            height: Some(self.config.height),
+                            offset_x: Some(0),
+                            offset_y: Some(0),
+                            delay_num: Some(0),
+                            delay_den: Some(0),
+                            dispose_op: Some(DisposeOp::ApngDisposeOpNone),
+                            blend_op: Some(BlendOp::ApngBlendOpSource),
+                        });
+                    }
+                    if frame.is_some() {
+                        data.extend_from_slice(&chunk.data);
+                    }
+                }
+                b"fdAT" => {
+                    if frame.is_none() || chunk.data.len() < 4 {
+                        return Err(APNGError::InvalidChunk("fdAT".to_string()));
+                    }
+                    self.check_seq_num(&chunk.data, "fdAT")?;
+                    data.extend_from_slice(&chunk.data[4..]);
+                }
+                b"IEND" => self.finished = true,
+                // texts may follow the image data
+                c_type => self.read_metadata(c_type, &chunk.data)?,
+            }
+        }
+
+        match frame {
+            Some(frame) => {
+                let image = self.decode_image(
+                    frame.width.unwrap_or(self.config.width),
+                    frame.height.unwrap_or(self.config.height),
+                    &data,
+                )?;
+                Ok(Some((image, frame)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn read_png_header(&mut self) -> APNGResult<()> {
+        let mut sig = [0; 8];
+        self.r.read_exact(&mut sig)?;
+        if &sig != b"\x89PNG\r\n\x1a\n" {
+            return Err(APNGError::InvalidSignature);
+        }
+        Ok(())
+    }
+
+    fn read_ihdr(&mut self, mut buf: &[u8]) -> APNGResult<()> {
+        let invalid = || APNGError::InvalidChunk("IHDR".to_string());
+        if buf.len() != 13 {
+            return Err(invalid());
+        }
+        self.config.width = buf.read_u32::<BigEndian>()?;
+        self.config.height = buf.read_u32::<BigEndian>()?;
+        self.config.depth = png::BitDepth::from_u8(buf[0]).ok_or_else(invalid)?;
+        self.config.color = png::ColorType::from_u8(buf[1]).ok_or_else(invalid)?;
+        self.config.validate_depth()?;
+        if self.config.width == 0 || self.config.height == 0 {
+            return Err(invalid());
+        }
+        self.config.interlace = match buf[4] {
+            0 => false,
+            1 => true,
+            _ => return Err(invalid()),
+        };
+        Ok(())
+    }
+
+    fn read_ac_tl(&mut self, mut buf: &[u8]) -> APNGResult<()> {
+        if buf.len() != 8 {
+            return Err(APNGError::InvalidChunk("acTL".to_string()));
+        }
+        self.config.num_frames = buf.read_u32::<BigEndian>()?;
+        self.config.num_plays = buf.read_u32::<BigEndian>()?;
+        self.animated = true;
+        Ok(())
+    }
+
+    fn read_plte(&mut self, buf: &[u8]) -> APNGResult<()> {
+        if buf.is_empty() || !buf.len().is_multiple_of(3) || buf.len() > 256 * 3 {
+            return Err(APNGError::InvalidChunk("PLTE".to_string()));
+        }
+        let colors = buf.chunks(3).map(|c| [c[0], c[1], c[2]]).collect();
+        self.config.palette = Some(Palette::new(colors));
+        Ok(())
+    }
+
+    // only the alpha of the palette is kept, the transparent color of other color types is skipped
+    fn read_t_rns(&mut self, buf: &[u8]) -> APNGResult<()> {
+        if self.config.color != png::ColorType::Indexed {
+            return Ok(());
+        }
+        match &mut self.config.palette {
+            Some(palette) if buf.len() <= palette.len() => {
+                palette.alpha = buf.to_vec();
+                Ok(())
+            }
+            _ => Err(APNGError::InvalidChunk("tRNS".to_string())),
+        }
+    }
+
+    fn read_fc_tl(&mut self, buf: &[u8]) -> APNGResult<Frame> {
+        let invalid = || APNGError::InvalidChunk("fcTL".to_string());
+        if buf.len() != 26 {
+            return Err(invalid());
+        }
+        self.check_seq_num(buf, "fcTL")?;
+
+        let mut buf = &buf[4..];
+        let width = buf.read_u32::<BigEndian>()?;
+        let height = buf.read_u32::<BigEndian>()?;
+        let offset_x = buf.read_u32::<BigEndian>()?;
+        let offset_y = buf.read_u32::<BigEndian>()?;
+        let delay_num = buf.read_u16::<BigEndian>()?;
+        let delay_den = buf.read_u16::<BigEndian>()?;
+        let dispose_op = match buf[0] {
+            0 => DisposeOp::ApngDisposeOpNone,
+            1 => DisposeOp::ApngDisposeOpBackground,
+            2 => DisposeOp::ApngDisposeOpPrevious,
+            _ => return Err(invalid()),
+        };
+        let blend_op = match buf[1] {
+            0 => BlendOp::ApngBlendOpSource,
+            1 => BlendOp::ApngBlendOpOver,
+            _ => return Err(invalid()),
+        };
+
+        if width == 0
+            || height == 0
+            || offset_x as u64 + width as u64 > self.config.width as u64
+            || offset_y as u64 + height as u64 > self.config.height as u64
+        {
+            return Err(invalid());
+        }
+
+        Ok(Frame {
+            width: Some(width),
+            height: Some(height),
+            offset_x: Some(offset_x),
+            offset_y: Some(offset_y),
+            delay_num: Some(delay_num),
+            delay_den: Some(delay_den),
+            dispose_op: Some(dispose_op),
+            blend_op: Some(blend_op),
+        })
+    }
+
+    // Unknown and invalid ancillary chunks are skipped, PNG allows decoders to ignore them.
+    // The encoder checks the metadata strictly instead.
+    fn read_metadata(&mut self, c_type: &[u8; 4], data: &[u8]) -> APNGResult<()> {
+        // an unknown critical chunk is needed to show the image
+        if c_type[0] & 0x20 == 0 {
+            return Err(APNGError::InvalidChunk(
+                String::from_utf8_lossy(c_type).into_owned(),
+            ));
+        }
+        let _ = self.config.metadata.read_chunk(c_type, data);
+        Ok(())
+    }
+
+    fn check_seq_num(&mut self, mut buf: &[u8], c_type: &str) -> APNGResult<()> {
+        if buf.read_u32::<BigEndian>()? != self.seq_num {
+            return Err(APNGError::InvalidChunk(c_type.to_string()));
+        }
+        self.seq_num += 1;
+        Ok(())
+    }
+
+    // inflate and unfilter the image data of a frame
+    fn decode_image(&self, width: u32, height: u32, data: &[u8]) -> APNGResult<PNGImage> {
+        let mut raw = Vec::new();
+        ZlibDecoder::new(data).read_to_end(&mut raw)?;
+        let mut raw = &raw[..];
+
+        let buf = if self.config.interlace {
+            let (w, h) = (width as usize, height as usize);
+            let mut passes = vec![];
+            for (pass_width, pass_height) in pass_sizes(w, h) {
+                // empty passes have no rows
+                let pass = match pass_width * pass_height {
+                    0 => vec![],
+                    _ => self.unfilter_rows(pass_width as u32, pass_height as u32, &mut raw)?,
+                };
+                passes.push(pass);
+            }
+            deinterlace(self.config.bits_per_pixel(), w, h, &passes)
+        } else {
+            self.unfilter_rows(width, height, &mut raw)?
+        };
+
+        Ok(PNGImage {
+            width,
+            height,
+            data: buf,
+            color_type: self.config.color,
+            bit_depth: self.config.depth,
+        })
+    }
+
+    // unfilter the rows of an image at the head of raw and advance it
+    fn unfilter_rows(&self, width: u32, height: u32, raw: &mut &[u8]) -> APNGResult<Vec<u8>> {
+        let bpp = self.config.bytes_per_pixel();
+        let row_len = self.config.raw_row_length_for(width) - 1;
+        let data_size = (row_len + 1) * height as usize;
+        if raw.len() < data_size {
+            return Err(APNGError::WrongDataSize(data_size, raw.len()));
+        }
+
+        let mut buf = Vec::with_capacity(row_len * height as usize);
+        let mut prev = vec![0; row_len];
+        for line in raw[..data_size].chunks(row_len + 1) {
+            let mut current = line[1..].to_vec();
+            unfilter(line[0], bpp, &prev, &mut current)?;
+            buf.extend_from_slice(&current);
+            prev = current;
+        }
+        *raw = &raw[data_size..];
+        Ok(buf)
+    }
+
+    // read chunk data 4 field and verify the CRC
+    fn read_chunk(&mut self) -> APNGResult<Chunk> {
+        let len = self.r.read_u32::<BigEndian>()?;
+        let mut c_type = [0; 4];
+        self.r.read_exact(&mut c_type)?;
+        let mut data = vec![];
+        (&mut self.r).take(len as u64).read_to_end(&mut data)?;
+        if data.len() != len as usize {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+
+        let mut crc = Crc::new();
+        crc.update(&c_type);
+        crc.update(&data);
+        if self.r.read_u32::<BigEndian>()? != crc.sum() {
+            return Err(APNGError::CrcMismatch(
+                String::from_utf8_lossy(&c_type).into_owned(),
+            ));
+        }
+        Ok(Chunk { c_type, data })
+    }
+}
+
+impl<R: io::Read> Iterator for Decoder<R> {
+    type Item = APNGResult<(PNGImage, Frame)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_frame().transpose()
+    }
+}
+
+fn unfilter(method: u8, bpp: usize, previous: &[u8], current: &mut [u8]) -> APNGResult<()> {
+    let len = current.len();
+
+    match method {
+        0 => (),
+        1 => {
+            for i in bpp..len {
+                current[i] = current[i].wrapping_add(current[i - bpp]);
+            }
+        }
+        2 => {
+            for i in 0..len {
+                current[i] = current[i].wrapping_add(previous[i]);
+            }
+        }
+        3 => {
+            for i in 0..bpp.min(len) {
+                current[i] = current[i].wrapping_add(previous[i] / 2);
+            }
+            for i in bpp..len {
+                let avg = (u16::from(current[i - bpp]) + u16::from(previous[i])) / 2;
+                current[i] = current[i].wrapping_add(avg as u8);
+            }
+        }
+        4 => {
+            for i in 0..bpp.min(len) {
+                current[i] = current[i].wrapping_add(previous[i]);
+            }
+            for i in bpp..len {
+                current[i] = current[i].wrapping_add(filter_path(
+                    current[i - bpp],
+                    previous[i],
+                    previous[i - bpp],
+                ));
+            }
+        }
+        _ => return Err(APNGError::InvalidFilterType(method)),
+    }
+    Ok(())
+}
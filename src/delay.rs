@@ -0,0 +1,126 @@
+use super::errors::{APNGError, APNGResult};
+use std::time::Duration;
+
+use crate::apng::Frame;
+
+// Returns the fraction num / den closest to the seconds with u16 terms, and its error in seconds.
+// The error is the fraction minus the seconds, a delay longer than 65535 seconds is clamped
+// and a negative or NaN delay is 0.
+pub fn delay_fraction(seconds: f64) -> (u16, u16, f64) {
+    let max = f64::from(u16::MAX);
+    if seconds.is_nan() || seconds <= 0.0 {
+        return (0, 1, 0.0 - seconds);
+    }
+    if seconds >= max {
+        return (u16::MAX, 1, max - seconds);
+    }
+
+    // convergents of the continued fraction, (numerator, denominator)
+    let (mut prev, mut cur) = ((0.0, 1.0), (1.0, 0.0));
+    let mut x = seconds;
+    let best = loop {
+        let a = x.floor();
+        let next = (a * cur.0 + prev.0, a * cur.1 + prev.1);
+        if next.0 > max || next.1 > max {
+            // the largest semiconvergent which fits may be closer than the last convergent
+            let t = ((max - prev.0) / cur.0).min((max - prev.1) / cur.1).floor();
+            let semi = (t * cur.0 + prev.0, t * cur.1 + prev.1);
+            let error = |(n, d): (f64, f64)| (n / d - seconds).abs();
+            break if t >= 1.0 && error(semi) < error(cur) {
+                semi
+            } else {
+                cur
+            };
+        }
+        (prev, cur) = (cur, next);
+        if x == a {
+            break cur;
+        }
+        x = 1.0 / (x - a);
+    };
+    let (num, den) = (best.0 as u16, best.1 as u16);
+    (num, den, f64::from(num) / f64::from(den) - seconds)
+}
+
+impl Frame {
+    // a frame shown for the delay, rounded to the closest fraction of u16 terms
+    pub fn with_delay(delay: Duration) -> Frame {
+        Self::with_delay_exact(delay).0
+    }
+
+    // with_delay, and the rounding error in seconds, the delay of the frame minus the given delay
+    pub fn with_delay_exact(delay: Duration) -> (Frame, f64) {
+        Self::with_seconds(delay.as_secs_f64())
+    }
+
+    // a frame of an animation of frames per second
+    pub fn from_fps(fps: f64) -> APNGResult<Frame> {
+        Ok(Self::from_fps_exact(fps)?.0)
+    }
+
+    // from_fps, and the rounding error of the delay in seconds
+    pub fn from_fps_exact(fps: f64) -> APNGResult<(Frame, f64)> {
+        if !fps.is_finite() || fps <= 0.0 {
+            return Err(APNGError::InvalidFrameRate(fps));
+        }
+        Ok(Self::with_seconds(1.0 / fps))
+    }
+
+    fn with_seconds(seconds: f64) -> (Frame, f64) {
+        let (num, den, error) = delay_fraction(seconds);
+        let frame = Frame {
+            delay_num: Some(num),
+            delay_den: Some(den),
+            ..Frame::default()
+        };
+        (frame, error)
+    }
+
+    // the delay of the frame, a denominator of 0 means 100
+    pub fn delay(&self) -> Option<Duration> {
+        let num = self.delay_num?;
+        let den = match self.delay_den? {
+            0 => 100,
+            den => den,
+        };
+        Some(Duration::from_secs_f64(f64::from(num) / f64::from(den)))
+    }
+}
+
+// Turns presentation timestamps into frame delays in ticks of the timescale.
+// Each delay is rounded from the end time of the frame, so the rounding errors don't add up and
+// the playback time stays within one tick of the timestamps.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Timeline {
+    // ticks per second, the denominator of the delays
+    timescale: u16,
+    // timestamp of the first frame
+    start: Option<Duration>,
+    // ticks of the delays returned
+    ticks: u64,
+}
+
+impl Timeline {
+    pub fn new(timescale: u16) -> Timeline {
+        Timeline {
+            timescale: timescale.max(1),
+            start: None,
+            ticks: 0,
+        }
+    }
+
+    // Returns the frame shown from the timestamp until the timestamp of the next frame.
+    // Frames must come in order, a delay longer than 65535 ticks is carried to the next frames.
+    pub fn frame(&mut self, pts: Duration, next_pts: Duration) -> Frame {
+        let start = *self.start.get_or_insert(pts);
+        let nanos = next_pts.saturating_sub(start).as_nanos() * u128::from(self.timescale);
+        let end = ((nanos + 500_000_000) / 1_000_000_000) as u64;
+        let num = end.saturating_sub(self.ticks).min(u64::from(u16::MAX));
+        self.ticks += num;
+        Frame {
+            delay_num: Some(num as u16),
+            delay_den: Some(self.timescale),
+            ..Frame::default()
+        }
+    }
+}
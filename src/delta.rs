@@ -0,0 +1,270 @@
+use super::errors::{APNGError, APNGResult};
+
+use crate::apng::{BlendOp, Config, DisposeOp, Frame, ImageBuffer};
+use crate::png::PNGImage;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DeltaMode {
+    // every frame is written with the whole canvas
+    #[default]
+    Off,
+    // each frame is cropped to the region changed from the previous frame
+    Crop,
+    // in addition to Crop, unchanged pixels of RGBA and GrayscaleAlpha frames
+    // are made transparent and drawn with ApngBlendOpOver
+    Transparent,
+}
+
+// keeps the previous frame to diff the next one against
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct DeltaState {
+    previous: Option<PNGImage>,
+}
+
+impl DeltaState {
+    // Returns the sub-frames which reproduce `image` on top of the previous frame.
+    // The smallest one after compression should be written, then the image is accepted.
+    pub(crate) fn candidates(
+        &self,
+        config: &Config,
+        image: PNGImage,
+        frame: Frame,
+    ) -> APNGResult<Vec<(PNGImage, Frame)>> {
+        if config.delta == DeltaMode::Off {
+            return Ok(vec![(image, frame)]);
+        }
+        if image.width != config.width || image.height != config.height {
+            return Err(APNGError::WrongFrameSize(
+                config.width,
+                config.height,
+                image.width,
+                image.height,
+            ));
+        }
+        let data_size = (config.raw_row_length() - 1) * config.height as usize;
+        if image.data.len() != data_size {
+            return Err(APNGError::WrongDataSize(data_size, image.data.len()));
+        }
+
+        // the canvas is always left as it is, so it equals the previous image
+        let full_frame = Frame {
+            width: Some(config.width),
+            height: Some(config.height),
+            offset_x: Some(0),
+            offset_y: Some(0),
+            dispose_op: Some(DisposeOp::ApngDisposeOpNone),
+            blend_op: Some(BlendOp::ApngBlendOpSource),
+            ..frame
+        };
+
+        let previous = match &self.previous {
+            Some(previous) => previous,
+            None => return Ok(vec![(image, full_frame)]),
+        };
+
+        let (x, y, width, height) = changed_region(config, previous, &image);
+        let cropped_frame = Frame {
+            width: Some(width),
+            height: Some(height),
+            offset_x: Some(x),
+            offset_y: Some(y),
+            ..full_frame.clone()
+        };
+
+        let mut candidates = vec![];
+        if config.delta == DeltaMode::Transparent
+            && let Some(transparent) = transparent(config, previous, &image, x, y, width, height)
+        {
+            let frame = Frame {
+                blend_op: Some(BlendOp::ApngBlendOpOver),
+                ..cropped_frame.clone()
+            };
+            candidates.push((transparent, frame));
+        }
+        if width != config.width || height != config.height {
+            candidates.push((crop(config, &image, x, y, width, height), cropped_frame));
+        }
+        candidates.push((image, full_frame));
+        Ok(candidates)
+    }
+
+    // the next frame is diffed against the image, once it is written
+    pub(crate) fn accept(&mut self, config: &Config, image: &PNGImage) {
+        if config.delta != DeltaMode::Off {
+            self.previous = Some(image.clone());
+        }
+    }
+
+    // candidates and accept at once, for the parallel encoders which can't reject a frame
+    pub(crate) fn advance(
+        &mut self,
+        config: &Config,
+        image: PNGImage,
+        frame: Frame,
+    ) -> APNGResult<Vec<(PNGImage, Frame)>> {
+        let previous = (config.delta != DeltaMode::Off).then(|| image.clone());
+        let candidates = self.candidates(config, image, frame)?;
+        if previous.is_some() {
+            self.previous = previous;
+        }
+        Ok(candidates)
+    }
+}
+
+// compress every candidate and returns the smallest one
+pub(crate) fn compress_smallest(
+    config: &Config,
+    candidates: Vec<(PNGImage, Frame)>,
+) -> APNGResult<(ImageBuffer, Frame)> {
+    let mut smallest: Option<(ImageBuffer, Frame)> = None;
+    for (image, frame) in candidates {
+        let image_buffer = ImageBuffer::new(config, &image)?;
+        if smallest
+            .as_ref()
+            .is_none_or(|(s, _)| image_buffer.data.len() < s.data.len())
+        {
+            smallest = Some((image_buffer, frame));
+        }
+    }
+    smallest.ok_or(APNGError::ImagesNotFound)
+}
+
+// reads the bits of a pixel, a pixel is 64 bits at most
+fn pixel(row: &[u8], x: usize, bits: usize) -> u64 {
+    if bits >= 8 {
+        let n = bits / 8;
+        row[x * n..(x + 1) * n]
+            .iter()
+            .fold(0, |acc, &b| (acc << 8) | u64::from(b))
+    } else {
+        let bit = x * bits;
+        u64::from((row[bit / 8] >> (8 - bits - bit % 8)) & ((1 << bits) - 1))
+    }
+}
+
+// Returns the bounding box (x, y, width, height) of the pixels which differ.
+// An unchanged frame still needs a region, so the first pixel is used.
+fn changed_region(
+    config: &Config,
+    previous: &PNGImage,
+    current: &PNGImage,
+) -> (u32, u32, u32, u32) {
+    let bits = config.bits_per_pixel();
+    let row_len = config.raw_row_length() - 1;
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (usize::MAX, usize::MAX, 0, 0);
+
+    let rows = previous
+        .data
+        .chunks(row_len)
+        .zip(current.data.chunks(row_len));
+    for (y, (prev_row, row)) in rows.enumerate() {
+        if prev_row == row {
+            continue;
+        }
+        for x in 0..config.width as usize {
+            if pixel(prev_row, x, bits) != pixel(row, x, bits) {
+                min_x = min_x.min(x);
+                max_x = max_x.max(x);
+                min_y = min_y.min(y);
+                max_y = max_y.max(y);
+            }
+        }
+    }
+
+    if min_x == usize::MAX {
+        return (0, 0, 1, 1);
+    }
+    (
+        min_x as u32,
+        min_y as u32,
+        (max_x - min_x + 1) as u32,
+        (max_y - min_y + 1) as u32,
+    )
+}
+
+// cut out a region of the image, packed pixels are shifted to the row start
+fn crop(config: &Config, image: &PNGImage, x: u32, y: u32, width: u32, height: u32) -> PNGImage {
+    let bits = config.bits_per_pixel();
+    let row_len = config.raw_row_length() - 1;
+    let out_len = config.raw_row_length_for(width) - 1;
+
+    let mut data = Vec::with_capacity(out_len * height as usize);
+    for row in image
+        .data
+        .chunks(row_len)
+        .skip(y as usize)
+        .take(height as usize)
+    {
+        if bits >= 8 {
+            let start = x as usize * bits / 8;
+            data.extend_from_slice(&row[start..start + out_len]);
+        } else {
+            let mut out = vec![0; out_len];
+            for i in 0..width as usize {
+                let v = pixel(row, x as usize + i, bits) as u8;
+                let bit = i * bits;
+                out[bit / 8] |= v << (8 - bits - bit % 8);
+            }
+            data.extend_from_slice(&out);
+        }
+    }
+
+    PNGImage {
+        width,
+        height,
+        data,
+        color_type: image.color_type,
+        bit_depth: image.bit_depth,
+    }
+}
+
+// Cut out a region of the image and clear the pixels unchanged from the previous frame.
+// Returns None unless every changed pixel is opaque, as ApngBlendOpOver would mix it.
+fn transparent(
+    config: &Config,
+    previous: &PNGImage,
+    image: &PNGImage,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+) -> Option<PNGImage> {
+    use png::ColorType::*;
+
+    if !matches!(config.color, Rgba | GrayscaleAlpha) {
+        return None;
+    }
+    let pixel_len = config.bits_per_pixel() / 8;
+    let alpha_len = config.depth as usize / 8;
+    let row_len = config.raw_row_length() - 1;
+    let start = x as usize * pixel_len;
+    let end = start + width as usize * pixel_len;
+
+    let mut data = Vec::with_capacity((end - start) * height as usize);
+    let rows = previous
+        .data
+        .chunks(row_len)
+        .zip(image.data.chunks(row_len));
+    for (prev_row, row) in rows.skip(y as usize).take(height as usize) {
+        let pixels = prev_row[start..end]
+            .chunks(pixel_len)
+            .zip(row[start..end].chunks(pixel_len));
+        for (prev_pixel, pixel) in pixels {
+            if prev_pixel == pixel {
+                data.extend(std::iter::repeat_n(0, pixel_len));
+            } else if pixel[pixel_len - alpha_len..].iter().all(|&v| v == 0xff) {
+                data.extend_from_slice(pixel);
+            } else {
+                return None;
+            }
+        }
+    }
+
+    Some(PNGImage {
+        width,
+        height,
+        data,
+        color_type: image.color_type,
+        bit_depth: image.bit_depth,
+    })
+}
@@ -13,6 +13,44 @@ pub enum APNGError {
     WrongDataSize(usize, usize),
     #[error("wrong frames nums, expected {0} got {1}")]
     WrongFrameNums(usize, usize),
+    #[error("wrong frame size, expected {0}x{1} got {2}x{3}")]
+    WrongFrameSize(u32, u32, u32, u32),
+    #[error("wrong color type, expected {0:?} {1:?} got {2:?} {3:?}")]
+    WrongColorType(png::ColorType, png::BitDepth, png::ColorType, png::BitDepth),
+    #[error("frame region is out of the canvas")]
+    FrameOutOfBounds,
+    #[error("the first frame must cover the whole canvas")]
+    InvalidFirstFrame,
+    #[error("invalid png signature")]
+    InvalidSignature,
+    #[error("invalid {0} chunk")]
+    InvalidChunk(String),
+    #[error("crc mismatch in {0} chunk")]
+    CrcMismatch(String),
+    #[error("invalid filter type {0}")]
+    InvalidFilterType(u8),
+    #[error("unsupported color type {0:?}")]
+    UnsupportedColorType(png::ColorType),
+    #[error("bit depth {1:?} is not allowed for {0:?}")]
+    InvalidBitDepth(png::ColorType, png::BitDepth),
+    #[error("palette is required for indexed color")]
+    PaletteNotFound,
+    #[error("invalid palette")]
+    InvalidPalette,
+    #[error("palette index {0} is out of range")]
+    PaletteIndexOutOfRange(usize),
+    #[error("invalid keyword {0:?}")]
+    InvalidKeyword(String),
+    #[error("invalid text of keyword {0:?}")]
+    InvalidText(String),
+    #[error("the position of the chunk is already written or not reached")]
+    InvalidChunkPosition,
+    #[error("the default image must be written before the frames")]
+    DefaultImageAfterFrames,
+    #[error("invalid frame rate {0}")]
+    InvalidFrameRate(f64),
+    #[error("a frame after a frame with a timestamp must have a timestamp")]
+    TimestampNotFound,
 }
 
 pub type AppResult<T> = Result<T, AppError>;
@@ -23,4 +61,8 @@ pub enum AppError {
     PNGImage(png::DecodingError),
     #[error("Unsupported image")]
     UnsupportedImage,
+    #[error("wrong data size, expected {0} got {1}")]
+    WrongDataSize(usize, usize),
+    #[error("sample {0} does not fit in the bit depth")]
+    SampleOutOfRange(u8),
 }
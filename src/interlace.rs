@@ -0,0 +1,75 @@
+// Adam7 passes, (x offset, y offset, x step, y step)
+const PASSES: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+// Returns the size of each pass in pixels, a pass may be empty for small images
+pub(crate) fn pass_sizes(width: usize, height: usize) -> [(usize, usize); 7] {
+    PASSES.map(|(x0, y0, dx, dy)| {
+        (
+            width.saturating_sub(x0).div_ceil(dx),
+            height.saturating_sub(y0).div_ceil(dy),
+        )
+    })
+}
+
+// split a deinterlaced image into the 7 passes, each one is packed like an image of its size
+pub(crate) fn interlace(bits: usize, width: usize, height: usize, data: &[u8]) -> Vec<Vec<u8>> {
+    let row_len = (width * bits).div_ceil(8);
+    PASSES
+        .iter()
+        .zip(pass_sizes(width, height))
+        .map(|(&(x0, y0, dx, dy), (w, h))| {
+            let pass_row_len = (w * bits).div_ceil(8);
+            let mut pass = vec![0; pass_row_len * h];
+            for py in 0..h {
+                let row = &data[(y0 + py * dy) * row_len..][..row_len];
+                let pass_row = &mut pass[py * pass_row_len..][..pass_row_len];
+                for px in 0..w {
+                    copy_pixel(row, x0 + px * dx, pass_row, px, bits);
+                }
+            }
+            pass
+        })
+        .collect()
+}
+
+// put the 7 passes back into a deinterlaced image
+pub(crate) fn deinterlace(bits: usize, width: usize, height: usize, passes: &[Vec<u8>]) -> Vec<u8> {
+    let row_len = (width * bits).div_ceil(8);
+    let mut data = vec![0; row_len * height];
+    for ((&(x0, y0, dx, dy), (w, h)), pass) in PASSES
+        .iter()
+        .zip(pass_sizes(width, height))
+        .zip(passes.iter())
+    {
+        let pass_row_len = (w * bits).div_ceil(8);
+        for py in 0..h {
+            let pass_row = &pass[py * pass_row_len..][..pass_row_len];
+            let row = &mut data[(y0 + py * dy) * row_len..][..row_len];
+            for px in 0..w {
+                copy_pixel(pass_row, px, row, x0 + px * dx, bits);
+            }
+        }
+    }
+    data
+}
+
+// copy the pixel at src_x to dst_x, dst must be zero filled for packed pixels
+fn copy_pixel(src: &[u8], src_x: usize, dst: &mut [u8], dst_x: usize, bits: usize) {
+    if bits >= 8 {
+        let n = bits / 8;
+        dst[dst_x * n..(dst_x + 1) * n].copy_from_slice(&src[src_x * n..(src_x + 1) * n]);
+    } else {
+        let src_bit = src_x * bits;
+        let v = (src[src_bit / 8] >> (8 - bits - src_bit % 8)) & ((1 << bits) - 1);
+        let dst_bit = dst_x * bits;
+        dst[dst_bit / 8] |= v << (8 - bits - dst_bit % 8);
+    }
+}
@@ -1,6 +1,29 @@
 mod apng;
+mod chunk;
+mod compress;
+mod decoder;
+mod delay;
+mod delta;
 pub mod errors;
+mod interlace;
+mod metadata;
+mod palette;
 mod png;
+mod quantize;
+mod reduce;
+mod renderer;
+mod stream;
 
 pub use crate::apng::*;
+pub use crate::chunk::*;
+pub use crate::compress::*;
+pub use crate::decoder::*;
+pub use crate::delay::{Timeline, delay_fraction};
+pub use crate::delta::DeltaMode;
+pub use crate::metadata::{Background, IccProfile, Metadata, PixelsPerUnit, Text, Time};
+pub use crate::palette::*;
 pub use crate::png::*;
+pub use crate::quantize::*;
+pub use crate::reduce::*;
+pub use crate::renderer::*;
+pub use crate::stream::*;
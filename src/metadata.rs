@@ -0,0 +1,484 @@
+use super::errors::{APNGError, APNGResult};
+use byteorder::{BigEndian, ReadBytesExt};
+use flate2::read::ZlibDecoder;
+use std::io::Read;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::apng::Config;
+
+// ancillary information of the image which is not a part of the frames
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Metadata {
+    // gAMA
+    pub gamma: Option<png::ScaledFloat>,
+    // cHRM
+    pub chromaticities: Option<png::SourceChromaticities>,
+    // sRGB, the image is in the sRGB color space with the rendering intent
+    pub srgb: Option<png::SrgbRenderingIntent>,
+    // iCCP, the profile is compressed when written
+    pub icc_profile: Option<IccProfile>,
+    // sBIT, the significant bits of each sample, 3 bytes for indexed color
+    pub significant_bits: Option<Vec<u8>>,
+    // bKGD
+    pub background: Option<Background>,
+    // pHYs
+    pub pixels_per_unit: Option<PixelsPerUnit>,
+    // tIME, the last modification time
+    pub time: Option<Time>,
+    // tEXt, zTXt and iTXt
+    pub texts: Vec<Text>,
+}
+
+// background color to show the image on, in the samples of the color type
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Background {
+    // Grayscale and GrayscaleAlpha
+    Gray(u16),
+    // Rgb and Rgba
+    Rgb(u16, u16, u16),
+    // index of the palette
+    Index(u8),
+}
+
+impl Background {
+    // read the data of bKGD, the size tells the color type
+    pub(crate) fn from_bytes(buf: &[u8]) -> Option<Self> {
+        let sample = |i: usize| u16::from_be_bytes([buf[i], buf[i + 1]]);
+        match buf.len() {
+            1 => Some(Background::Index(buf[0])),
+            2 => Some(Background::Gray(sample(0))),
+            6 => Some(Background::Rgb(sample(0), sample(2), sample(4))),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PixelsPerUnit {
+    pub x: u32,
+    pub y: u32,
+    // the aspect ratio only for png::Unit::Unspecified
+    pub unit: png::Unit,
+}
+
+impl PixelsPerUnit {
+    pub fn from_dpi(dpi: u32) -> Self {
+        // 1 inch is 0.0254 meter
+        let ppm = (f64::from(dpi) / 0.0254).round() as u32;
+        PixelsPerUnit {
+            x: ppm,
+            y: ppm,
+            unit: png::Unit::Meter,
+        }
+    }
+}
+
+// UTC time
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Time {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    // 60 for a leap second
+    pub second: u8,
+}
+
+impl Time {
+    pub fn now() -> Self {
+        Self::from_system_time(SystemTime::now())
+    }
+
+    pub fn from_system_time(time: SystemTime) -> Self {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let (days, secs) = ((secs / 86400) as i64, secs % 86400);
+
+        // civil date from the days since 1970-01-01
+        let z = days + 719468;
+        let era = z / 146097;
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+
+        Time {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (secs / 3600) as u8,
+            minute: (secs / 60 % 60) as u8,
+            second: (secs % 60) as u8,
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        (1..=12).contains(&self.month)
+            && (1..=31).contains(&self.day)
+            && self.hour <= 23
+            && self.minute <= 59
+            && self.second <= 60
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IccProfile {
+    // Latin-1 keyword of 1 to 79 bytes
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+// keyword and value pair, the keyword is 1 to 79 Latin-1 characters
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Text {
+    // tEXt
+    Latin1 {
+        keyword: String,
+        text: String,
+    },
+    // zTXt
+    Compressed {
+        keyword: String,
+        text: String,
+    },
+    // iTXt
+    Utf8 {
+        keyword: String,
+        // language of the text like "en-US", may be empty
+        language_tag: String,
+        // keyword translated into the language, may be empty
+        translated_keyword: String,
+        text: String,
+        compressed: bool,
+    },
+}
+
+impl Text {
+    pub fn keyword(&self) -> &str {
+        match self {
+            Text::Latin1 { keyword, .. }
+            | Text::Compressed { keyword, .. }
+            | Text::Utf8 { keyword, .. } => keyword,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        match self {
+            Text::Latin1 { text, .. } | Text::Compressed { text, .. } | Text::Utf8 { text, .. } => {
+                text
+            }
+        }
+    }
+
+    // Returns the chunk (type, data) after checking the keyword and the text
+    pub(crate) fn chunk(&self, config: &Config) -> APNGResult<([u8; 4], Vec<u8>)> {
+        let invalid = || APNGError::InvalidText(self.keyword().to_string());
+        let mut buf = keyword(self.keyword())?;
+        buf.push(0);
+        match self {
+            Text::Latin1 { text, .. } => {
+                buf.extend(latin1(text).ok_or_else(invalid)?);
+                Ok((*b"tEXt", buf))
+            }
+            Text::Compressed { text, .. } => {
+                let text = latin1(text).ok_or_else(invalid)?;
+                buf.push(0); // compression method
+                buf.extend(config.deflater.deflate(&text, config.compression)?);
+                Ok((*b"zTXt", buf))
+            }
+            Text::Utf8 {
+                language_tag,
+                translated_keyword,
+                text,
+                compressed,
+                ..
+            } => {
+                let valid_tag = language_tag
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-');
+                if !valid_tag || translated_keyword.contains('\0') || text.contains('\0') {
+                    return Err(invalid());
+                }
+                buf.extend_from_slice(&[u8::from(*compressed), 0]);
+                buf.extend_from_slice(language_tag.as_bytes());
+                buf.push(0);
+                buf.extend_from_slice(translated_keyword.as_bytes());
+                buf.push(0);
+                if *compressed {
+                    buf.extend(
+                        config
+                            .deflater
+                            .deflate(text.as_bytes(), config.compression)?,
+                    );
+                } else {
+                    buf.extend_from_slice(text.as_bytes());
+                }
+                Ok((*b"iTXt", buf))
+            }
+        }
+    }
+
+    // read tEXt, zTXt or iTXt
+    fn read_chunk(c_type: &[u8; 4], buf: &[u8]) -> APNGResult<Text> {
+        let invalid = || APNGError::InvalidChunk(String::from_utf8_lossy(c_type).into_owned());
+        let inflate = |data: &[u8]| -> APNGResult<Vec<u8>> {
+            let mut buf = vec![];
+            ZlibDecoder::new(data).read_to_end(&mut buf)?;
+            Ok(buf)
+        };
+        let utf8 = |data: Vec<u8>| String::from_utf8(data).map_err(|_| invalid());
+
+        let (keyword, rest) = split_keyword(buf).ok_or_else(invalid)?;
+        match c_type {
+            b"tEXt" => Ok(Text::Latin1 {
+                keyword,
+                text: rest.iter().map(|&b| char::from(b)).collect(),
+            }),
+            b"zTXt" => match rest.split_first() {
+                Some((0, data)) => Ok(Text::Compressed {
+                    keyword,
+                    text: inflate(data)?.into_iter().map(char::from).collect(),
+                }),
+                _ => Err(invalid()),
+            },
+            _ => {
+                // compression flag and method
+                let (compressed, rest) = match rest {
+                    [0, _, rest @ ..] => (false, rest),
+                    [1, 0, rest @ ..] => (true, rest),
+                    _ => return Err(invalid()),
+                };
+                let mut fields = rest.splitn(3, |&b| b == 0);
+                let (language_tag, translated_keyword, text) =
+                    match (fields.next(), fields.next(), fields.next()) {
+                        (Some(tag), Some(translated), Some(text)) => (tag, translated, text),
+                        _ => return Err(invalid()),
+                    };
+                let text = match compressed {
+                    true => inflate(text)?,
+                    false => text.to_vec(),
+                };
+                Ok(Text::Utf8 {
+                    keyword,
+                    language_tag: utf8(language_tag.to_vec())?,
+                    translated_keyword: utf8(translated_keyword.to_vec())?,
+                    text: utf8(text)?,
+                    compressed,
+                })
+            }
+        }
+    }
+}
+
+impl Metadata {
+    // Returns the chunks (type, data) which must be written before PLTE and the image data
+    pub(crate) fn chunks_before_plte(
+        &self,
+        config: &Config,
+    ) -> APNGResult<Vec<([u8; 4], Vec<u8>)>> {
+        let mut chunks = vec![];
+        if let Some(chromaticities) = self.chromaticities {
+            chunks.push((*b"cHRM", chromaticities.to_be_bytes().to_vec()));
+        }
+        if let Some(gamma) = self.gamma {
+            chunks.push((*b"gAMA", gamma.into_scaled().to_be_bytes().to_vec()));
+        }
+        if let Some(profile) = &self.icc_profile {
+            // an image with iCCP must not have sRGB
+            if self.srgb.is_some() {
+                return Err(APNGError::InvalidChunk("iCCP".to_string()));
+            }
+            let mut buf = keyword(&profile.name)?;
+            // null separator and the compression method
+            buf.extend_from_slice(&[0, 0]);
+            buf.extend(config.deflater.deflate(&profile.data, config.compression)?);
+            chunks.push((*b"iCCP", buf));
+        }
+        if let Some(srgb) = self.srgb {
+            chunks.push((*b"sRGB", vec![srgb as u8]));
+        }
+        if let Some(bits) = &self.significant_bits {
+            let (samples, max) = match config.color {
+                png::ColorType::Indexed => (3, 8),
+                color => (color.samples(), config.depth as u8),
+            };
+            if bits.len() != samples || bits.iter().any(|&b| b == 0 || b > max) {
+                return Err(APNGError::InvalidChunk("sBIT".to_string()));
+            }
+            chunks.push((*b"sBIT", bits.clone()));
+        }
+        Ok(chunks)
+    }
+
+    // Returns the chunks (type, data) which must be written after PLTE and before the image data
+    pub(crate) fn chunks_after_plte(&self, config: &Config) -> APNGResult<Vec<([u8; 4], Vec<u8>)>> {
+        use png::ColorType::*;
+
+        let mut chunks = vec![];
+        if let Some(background) = self.background {
+            let max = ((1u32 << config.depth as u32) - 1) as u16;
+            let palette_len = config.palette.as_ref().map_or(0, |p| p.len());
+            let buf = match (background, config.color) {
+                (Background::Gray(v), Grayscale | GrayscaleAlpha) if v <= max => {
+                    v.to_be_bytes().to_vec()
+                }
+                (Background::Rgb(r, g, b), Rgb | Rgba) if r.max(g).max(b) <= max => {
+                    [r, g, b].iter().flat_map(|v| v.to_be_bytes()).collect()
+                }
+                (Background::Index(i), Indexed) if usize::from(i) < palette_len => vec![i],
+                _ => return Err(APNGError::InvalidChunk("bKGD".to_string())),
+            };
+            chunks.push((*b"bKGD", buf));
+        }
+        if let Some(p) = self.pixels_per_unit {
+            let mut buf = vec![];
+            buf.extend_from_slice(&p.x.to_be_bytes());
+            buf.extend_from_slice(&p.y.to_be_bytes());
+            buf.push(p.unit as u8);
+            chunks.push((*b"pHYs", buf));
+        }
+        if let Some(t) = self.time {
+            if !t.is_valid() {
+                return Err(APNGError::InvalidChunk("tIME".to_string()));
+            }
+            let mut buf = t.year.to_be_bytes().to_vec();
+            buf.extend_from_slice(&[t.month, t.day, t.hour, t.minute, t.second]);
+            chunks.push((*b"tIME", buf));
+        }
+        Ok(chunks)
+    }
+
+    // Reads the chunk if it is metadata, returns false for other chunks
+    pub(crate) fn read_chunk(&mut self, c_type: &[u8; 4], mut buf: &[u8]) -> APNGResult<bool> {
+        let invalid = || APNGError::InvalidChunk(String::from_utf8_lossy(c_type).into_owned());
+        match c_type {
+            b"tEXt" | b"zTXt" | b"iTXt" => self.texts.push(Text::read_chunk(c_type, buf)?),
+            b"gAMA" => {
+                if buf.len() != 4 {
+                    return Err(invalid());
+                }
+                self.gamma = Some(png::ScaledFloat::from_scaled(buf.read_u32::<BigEndian>()?));
+            }
+            b"cHRM" => {
+                if buf.len() != 32 {
+                    return Err(invalid());
+                }
+                let mut point = || -> APNGResult<(png::ScaledFloat, png::ScaledFloat)> {
+                    let x = png::ScaledFloat::from_scaled(buf.read_u32::<BigEndian>()?);
+                    let y = png::ScaledFloat::from_scaled(buf.read_u32::<BigEndian>()?);
+                    Ok((x, y))
+                };
+                self.chromaticities = Some(png::SourceChromaticities {
+                    white: point()?,
+                    red: point()?,
+                    green: point()?,
+                    blue: point()?,
+                });
+            }
+            b"sRGB" => {
+                use png::SrgbRenderingIntent::*;
+                self.srgb = Some(match buf {
+                    [0] => Perceptual,
+                    [1] => RelativeColorimetric,
+                    [2] => Saturation,
+                    [3] => AbsoluteColorimetric,
+                    _ => return Err(invalid()),
+                });
+            }
+            b"iCCP" => {
+                let (name, rest) = split_keyword(buf).ok_or_else(invalid)?;
+                // only zlib is defined for the compression method
+                if rest.first() != Some(&0) {
+                    return Err(invalid());
+                }
+                let mut data = vec![];
+                ZlibDecoder::new(&rest[1..]).read_to_end(&mut data)?;
+                self.icc_profile = Some(IccProfile { name, data });
+            }
+            b"sBIT" => {
+                if buf.is_empty() || buf.len() > 4 {
+                    return Err(invalid());
+                }
+                self.significant_bits = Some(buf.to_vec());
+            }
+            b"bKGD" => self.background = Some(Background::from_bytes(buf).ok_or_else(invalid)?),
+            b"pHYs" => {
+                if buf.len() != 9 {
+                    return Err(invalid());
+                }
+                self.pixels_per_unit = Some(PixelsPerUnit {
+                    x: buf.read_u32::<BigEndian>()?,
+                    y: buf.read_u32::<BigEndian>()?,
+                    unit: png::Unit::from_u8(buf[0]).ok_or_else(invalid)?,
+                });
+            }
+            b"tIME" => {
+                if buf.len() != 7 {
+                    return Err(invalid());
+                }
+                let time = Time {
+                    year: buf.read_u16::<BigEndian>()?,
+                    month: buf[0],
+                    day: buf[1],
+                    hour: buf[2],
+                    minute: buf[3],
+                    second: buf[4],
+                };
+                if !time.is_valid() {
+                    return Err(invalid());
+                }
+                self.time = Some(time);
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+// Checks the keyword of 1 to 79 printable Latin-1 characters without leading, trailing
+// or consecutive spaces, and returns it as Latin-1 bytes.
+pub(crate) fn keyword(keyword: &str) -> APNGResult<Vec<u8>> {
+    let invalid = || APNGError::InvalidKeyword(keyword.to_string());
+    let bytes = keyword
+        .chars()
+        .map(|c| match c as u32 {
+            0x20..=0x7e | 0xa1..=0xff => Ok(c as u8),
+            _ => Err(invalid()),
+        })
+        .collect::<APNGResult<Vec<u8>>>()?;
+    if bytes.is_empty()
+        || bytes.len() > 79
+        || bytes[0] == b' '
+        || bytes[bytes.len() - 1] == b' '
+        || bytes.windows(2).any(|w| w == b"  ")
+    {
+        return Err(invalid());
+    }
+    Ok(bytes)
+}
+
+// Returns the Latin-1 bytes of the text, None if it has other characters or null
+fn latin1(text: &str) -> Option<Vec<u8>> {
+    text.chars()
+        .map(|c| match c as u32 {
+            1..=0xff => Some(c as u8),
+            _ => None,
+        })
+        .collect()
+}
+
+// split the data at the null separator after the Latin-1 keyword
+pub(crate) fn split_keyword(buf: &[u8]) -> Option<(String, &[u8])> {
+    let end = buf.iter().position(|&b| b == 0)?;
+    if end == 0 || end > 79 {
+        return None;
+    }
+    let keyword = buf[..end].iter().map(|&b| char::from(b)).collect();
+    Some((keyword, &buf[end + 1..]))
+}
@@ -0,0 +1,59 @@
+use super::errors::{APNGError, APNGResult};
+
+// global palette of an indexed color animation, shared by every frame
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Palette {
+    // written to PLTE
+    pub colors: Vec<[u8; 3]>,
+    // alpha of each entry written to tRNS, missing entries are opaque
+    pub alpha: Vec<u8>,
+}
+
+impl Palette {
+    pub fn new(colors: Vec<[u8; 3]>) -> Self {
+        Palette {
+            colors,
+            alpha: vec![],
+        }
+    }
+
+    // make Palette from RGBA colors, the trailing opaque entries are not written to tRNS
+    pub fn from_rgba(colors: &[[u8; 4]]) -> Self {
+        let mut alpha: Vec<u8> = colors.iter().map(|c| c[3]).collect();
+        while alpha.last() == Some(&0xff) {
+            alpha.pop();
+        }
+        Palette {
+            colors: colors.iter().map(|c| [c[0], c[1], c[2]]).collect(),
+            alpha,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+
+    // Returns the RGBA color of the entry
+    pub fn rgba(&self, index: usize) -> Option<[u8; 4]> {
+        let [r, g, b] = *self.colors.get(index)?;
+        Some([r, g, b, self.alpha.get(index).copied().unwrap_or(0xff)])
+    }
+
+    // check the palette can be written for images of the bit depth
+    pub(crate) fn validate(&self, depth: png::BitDepth) -> APNGResult<()> {
+        let max = (1usize << (depth as usize).min(8)).min(256);
+        if self.colors.is_empty() || self.colors.len() > max || self.alpha.len() > self.len() {
+            return Err(APNGError::InvalidPalette);
+        }
+        Ok(())
+    }
+
+    // PLTE chunk data
+    pub(crate) fn plte(&self) -> Vec<u8> {
+        self.colors.iter().flatten().copied().collect()
+    }
+}
@@ -3,6 +3,8 @@ use image::{DynamicImage, GenericImageView};
 use png::BitDepth;
 use std::{fs::File, io};
 
+use crate::metadata::{Background, IccProfile, Metadata, PixelsPerUnit, Text};
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct PNGImage {
     pub width: u32,
@@ -12,6 +14,95 @@ pub struct PNGImage {
     pub bit_depth: png::BitDepth,
 }
 
+impl PNGImage {
+    // make 16 bit PNGImage from samples, they are stored in big endian as PNG does
+    pub fn from_samples16(
+        width: u32,
+        height: u32,
+        color_type: png::ColorType,
+        samples: &[u16],
+    ) -> AppResult<PNGImage> {
+        if color_type == png::ColorType::Indexed {
+            return Err(AppError::UnsupportedImage);
+        }
+        let len = width as usize * height as usize * color_type.samples();
+        if samples.len() != len {
+            return Err(AppError::WrongDataSize(len, samples.len()));
+        }
+
+        Ok(PNGImage {
+            width,
+            height,
+            data: vec16_to_vec8(samples),
+            color_type,
+            bit_depth: BitDepth::Sixteen,
+        })
+    }
+
+    // make 1 bit grayscale PNGImage, true is white
+    pub fn from_bitmap(width: u32, height: u32, pixels: &[bool]) -> AppResult<PNGImage> {
+        let samples: Vec<u8> = pixels.iter().map(|&p| u8::from(p)).collect();
+        Self::from_packed(
+            width,
+            height,
+            png::ColorType::Grayscale,
+            BitDepth::One,
+            &samples,
+        )
+    }
+
+    // make grayscale PNGImage from one sample of the bit depth per pixel, 1/2/4/8 bits
+    pub fn from_gray(
+        width: u32,
+        height: u32,
+        bit_depth: BitDepth,
+        samples: &[u8],
+    ) -> AppResult<PNGImage> {
+        Self::from_packed(width, height, png::ColorType::Grayscale, bit_depth, samples)
+    }
+
+    // make indexed PNGImage from one palette index per pixel, 1/2/4/8 bits
+    pub fn from_indices(
+        width: u32,
+        height: u32,
+        bit_depth: BitDepth,
+        indices: &[u8],
+    ) -> AppResult<PNGImage> {
+        Self::from_packed(width, height, png::ColorType::Indexed, bit_depth, indices)
+    }
+
+    // pack the samples into rows, the last byte of each row is padded with 0
+    fn from_packed(
+        width: u32,
+        height: u32,
+        color_type: png::ColorType,
+        bit_depth: BitDepth,
+        samples: &[u8],
+    ) -> AppResult<PNGImage> {
+        // PNG has no empty image, and the rows can't be packed
+        if bit_depth == BitDepth::Sixteen || width == 0 || height == 0 {
+            return Err(AppError::UnsupportedImage);
+        }
+        let len = width as usize * height as usize;
+        if samples.len() != len {
+            return Err(AppError::WrongDataSize(len, samples.len()));
+        }
+        let depth = bit_depth as usize;
+        if let Some(&v) = samples.iter().find(|&&v| u16::from(v) >= 1 << depth) {
+            return Err(AppError::SampleOutOfRange(v));
+        }
+
+        let samples: Vec<u16> = samples.iter().map(|&v| u16::from(v)).collect();
+        Ok(PNGImage {
+            width,
+            height,
+            data: pack_samples(&samples, depth, width as usize),
+            color_type,
+            bit_depth,
+        })
+    }
+}
+
 // make PNGImage from image::DynamicImage.
 pub fn load_dynamic_image(img: image::DynamicImage) -> AppResult<PNGImage> {
     let (width, height) = img.dimensions();
@@ -28,6 +119,13 @@ pub fn load_dynamic_image(img: image::DynamicImage) -> AppResult<PNGImage> {
 
 // make PNGImage from png image decoder
 pub fn load_png(filepath: &str) -> AppResult<PNGImage> {
+    load_png_with_metadata(filepath).map(|(image, _)| image)
+}
+
+// make PNGImage and its metadata from png image decoder
+// the png decoder doesn't keep the name of iCCP, the profile is named "ICC profile"
+// and tIME is not read
+pub fn load_png_with_metadata(filepath: &str) -> AppResult<(PNGImage, Metadata)> {
     let file = File::open(filepath).unwrap();
     let reader = io::BufReader::new(file);
     let decoder = png::Decoder::new(reader);
@@ -38,20 +136,91 @@ pub fn load_png(filepath: &str) -> AppResult<PNGImage> {
     // read the frame
     let info = reader.next_frame(&mut buf).unwrap();
 
-    Ok(PNGImage {
-        width: info.width,
-        height: info.height,
-        data: buf,
-        color_type: info.color_type,
-        bit_depth: info.bit_depth,
-    })
+    let png_info = reader.info();
+    // the texts before the image data
+    let mut texts = vec![];
+    for t in png_info.uncompressed_latin1_text.iter() {
+        texts.push(Text::Latin1 {
+            keyword: t.keyword.clone(),
+            text: t.text.clone(),
+        });
+    }
+    for t in png_info.compressed_latin1_text.iter() {
+        texts.push(Text::Compressed {
+            keyword: t.keyword.clone(),
+            text: t.get_text().map_err(AppError::PNGImage)?,
+        });
+    }
+    for t in png_info.utf8_text.iter() {
+        texts.push(Text::Utf8 {
+            keyword: t.keyword.clone(),
+            language_tag: t.language_tag.clone(),
+            translated_keyword: t.translated_keyword.clone(),
+            text: t.get_text().map_err(AppError::PNGImage)?,
+            compressed: t.compressed,
+        });
+    }
+    let metadata = Metadata {
+        gamma: png_info.gama_chunk,
+        chromaticities: png_info.chrm_chunk,
+        srgb: png_info.srgb,
+        icc_profile: png_info.icc_profile.as_ref().map(|data| IccProfile {
+            name: "ICC profile".to_string(),
+            data: data.to_vec(),
+        }),
+        significant_bits: png_info.sbit.as_ref().map(|bits| bits.to_vec()),
+        background: png_info
+            .bkgd
+            .as_ref()
+            .and_then(|buf| Background::from_bytes(buf)),
+        pixels_per_unit: png_info.pixel_dims.map(|dims| PixelsPerUnit {
+            x: dims.xppu,
+            y: dims.yppu,
+            unit: dims.unit,
+        }),
+        // tIME is not kept by the decoder
+        time: None,
+        texts,
+    };
+
+    Ok((
+        PNGImage {
+            width: info.width,
+            height: info.height,
+            data: buf,
+            color_type: info.color_type,
+            bit_depth: info.bit_depth,
+        },
+        metadata,
+    ))
+}
+
+/// Pack samples of the bit depth into rows, each row starts at a byte boundary
+pub(crate) fn pack_samples(samples: &[u16], depth: usize, row_samples: usize) -> Vec<u8> {
+    let row_len = (row_samples * depth).div_ceil(8);
+    let mut output = Vec::with_capacity(row_len * samples.len() / row_samples.max(1));
+    for row in samples.chunks(row_samples) {
+        match depth {
+            16 => row.iter().for_each(|v| output.extend(&v.to_be_bytes())),
+            8 => output.extend(row.iter().map(|&v| v as u8)),
+            _ => {
+                let mut packed = vec![0; row_len];
+                for (i, &v) in row.iter().enumerate() {
+                    let bit = i * depth;
+                    packed[bit / 8] |= (v as u8) << (8 - depth - bit % 8);
+                }
+                output.extend(packed);
+            }
+        }
+    }
+    output
 }
 
-/// Safely convert a Vec<u16> to a Vec<u8>
-fn vec16_to_vec8(input: Vec<u16>) -> Vec<u8> {
+/// Safely convert 16 bit samples to big endian bytes, the byte order of PNG
+fn vec16_to_vec8(input: &[u16]) -> Vec<u8> {
     let mut output = Vec::with_capacity(input.len() * 2);
     for nb in input {
-        output.extend(&nb.to_le_bytes());
+        output.extend(&nb.to_be_bytes());
     }
     output
 }
@@ -68,20 +237,20 @@ fn get_raw_buffer_dynamic_image(
         DynamicImage::ImageLumaA8(image) => Ok((image.into_raw(), GrayscaleAlpha, BitDepth::Eight)),
         DynamicImage::ImageRgba8(image) => Ok((image.into_raw(), Rgba, BitDepth::Eight)),
         DynamicImage::ImageLuma16(image) => Ok((
-            vec16_to_vec8(image.into_raw()),
+            vec16_to_vec8(&image.into_raw()),
             Grayscale,
             BitDepth::Sixteen,
         )),
         DynamicImage::ImageLumaA16(image) => Ok((
-            vec16_to_vec8(image.into_raw()),
+            vec16_to_vec8(&image.into_raw()),
             GrayscaleAlpha,
             BitDepth::Sixteen,
         )),
         DynamicImage::ImageRgb16(image) => {
-            Ok((vec16_to_vec8(image.into_raw()), Rgb, BitDepth::Sixteen))
+            Ok((vec16_to_vec8(&image.into_raw()), Rgb, BitDepth::Sixteen))
         }
         DynamicImage::ImageRgba16(image) => {
-            Ok((vec16_to_vec8(image.into_raw()), Rgba, BitDepth::Sixteen))
+            Ok((vec16_to_vec8(&image.into_raw()), Rgba, BitDepth::Sixteen))
         }
         _ => Err(AppError::UnsupportedImage),
     }
@@ -0,0 +1,232 @@
+use super::errors::{APNGError, APNGResult};
+use std::collections::HashMap;
+
+use crate::palette::Palette;
+use crate::png::PNGImage;
+
+// reduces the colors of RGB and RGBA frames to one palette shared by the animation
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Quantizer {
+    // number of palette entries, 2 to 256
+    pub max_colors: usize,
+    // diffuse the quantization error with Floyd-Steinberg dithering
+    pub dither: bool,
+}
+
+impl Default for Quantizer {
+    fn default() -> Self {
+        Quantizer {
+            max_colors: 256,
+            dither: false,
+        }
+    }
+}
+
+impl Quantizer {
+    // Computes a palette over all the frames by median cut and converts them to 8 bit indexed images.
+    // The colors are kept as they are when all of them fit in the palette.
+    pub fn quantize(&self, images: &[PNGImage]) -> APNGResult<(Vec<PNGImage>, Palette)> {
+        if images.is_empty() {
+            return Err(APNGError::ImagesNotFound);
+        }
+        // an empty frame can't be written, like in the encoder
+        if images
+            .iter()
+            .any(|image| image.width == 0 || image.height == 0)
+        {
+            return Err(APNGError::FrameOutOfBounds);
+        }
+        if !(2..=256).contains(&self.max_colors) {
+            return Err(APNGError::InvalidPalette);
+        }
+        let frames = images
+            .iter()
+            .map(to_rgba8)
+            .collect::<APNGResult<Vec<_>>>()?;
+
+        let mut histogram: HashMap<[u8; 4], u32> = HashMap::new();
+        for frame in frames.iter() {
+            for pixel in frame.chunks(4) {
+                *histogram.entry(normalize(pixel)).or_default() += 1;
+            }
+        }
+        let mut colors = median_cut(histogram.into_iter().collect(), self.max_colors);
+        // transparent entries first, so that tRNS only holds them
+        colors.sort_by_key(|c| (c[3] == 0xff, *c));
+        colors.dedup();
+
+        let mut nearest = Nearest {
+            colors: &colors,
+            cache: HashMap::new(),
+        };
+        let images = images
+            .iter()
+            .zip(frames.iter())
+            .map(|(image, rgba)| {
+                let data = if self.dither {
+                    dither(&mut nearest, rgba, image.width as usize)
+                } else {
+                    rgba.chunks(4)
+                        .map(|p| nearest.index(normalize(p)))
+                        .collect()
+                };
+                PNGImage {
+                    width: image.width,
+                    height: image.height,
+                    data,
+                    color_type: png::ColorType::Indexed,
+                    bit_depth: png::BitDepth::Eight,
+                }
+            })
+            .collect();
+        Ok((images, Palette::from_rgba(&colors)))
+    }
+}
+
+fn to_rgba8(image: &PNGImage) -> APNGResult<Vec<u8>> {
+    if image.bit_depth != png::BitDepth::Eight {
+        return Err(APNGError::UnsupportedColorType(image.color_type));
+    }
+    match image.color_type {
+        png::ColorType::Rgba => Ok(image.data.clone()),
+        png::ColorType::Rgb => Ok(image
+            .data
+            .chunks(3)
+            .flat_map(|p| [p[0], p[1], p[2], 0xff])
+            .collect()),
+        color => Err(APNGError::UnsupportedColorType(color)),
+    }
+}
+
+// every fully transparent pixel looks the same
+fn normalize(pixel: &[u8]) -> [u8; 4] {
+    match pixel[3] {
+        0 => [0; 4],
+        _ => [pixel[0], pixel[1], pixel[2], pixel[3]],
+    }
+}
+
+// Splits the box with the widest channel at its weighted median until there are enough boxes,
+// then returns the weighted average color of each box.
+fn median_cut(colors: Vec<([u8; 4], u32)>, max_colors: usize) -> Vec<[u8; 4]> {
+    if colors.len() <= max_colors {
+        return colors.into_iter().map(|(c, _)| c).collect();
+    }
+
+    let mut boxes = vec![colors];
+    while boxes.len() < max_colors {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .map(|(i, b)| {
+                let (channel, range) = widest_channel(b);
+                (range, i, channel)
+            })
+            .max();
+        let (_, i, channel) = match widest {
+            Some(v) => v,
+            None => break,
+        };
+
+        let mut b = boxes.swap_remove(i);
+        b.sort_unstable_by_key(|(c, _)| c[channel]);
+        let total: u64 = b.iter().map(|&(_, n)| u64::from(n)).sum();
+        let mut count = 0;
+        let mut median = b.len() - 1;
+        for (j, &(_, n)) in b.iter().enumerate() {
+            count += u64::from(n);
+            if count * 2 >= total {
+                median = j;
+                break;
+            }
+        }
+        // both halves must have a color
+        let at = (median + 1).clamp(1, b.len() - 1);
+        let rest = b.split_off(at);
+        boxes.push(b);
+        boxes.push(rest);
+    }
+
+    boxes.iter().map(|b| average(b)).collect()
+}
+
+// Returns the channel with the largest range in the box and the range
+fn widest_channel(colors: &[([u8; 4], u32)]) -> (usize, u8) {
+    (0..4)
+        .map(|channel| {
+            let min = colors.iter().map(|(c, _)| c[channel]).min().unwrap_or(0);
+            let max = colors.iter().map(|(c, _)| c[channel]).max().unwrap_or(0);
+            (channel, max - min)
+        })
+        .max_by_key(|&(_, range)| range)
+        .unwrap()
+}
+
+fn average(colors: &[([u8; 4], u32)]) -> [u8; 4] {
+    let total: u64 = colors.iter().map(|&(_, n)| u64::from(n)).sum();
+    let mut sum = [0u64; 4];
+    for (c, n) in colors {
+        for (s, &v) in sum.iter_mut().zip(c.iter()) {
+            *s += u64::from(v) * u64::from(*n);
+        }
+    }
+    sum.map(|s| ((s + total / 2) / total) as u8)
+}
+
+// finds the closest palette entry, the results are cached as frames repeat colors
+struct Nearest<'a> {
+    colors: &'a [[u8; 4]],
+    cache: HashMap<[u8; 4], u8>,
+}
+
+impl Nearest<'_> {
+    fn index(&mut self, color: [u8; 4]) -> u8 {
+        let colors = self.colors;
+        *self.cache.entry(color).or_insert_with(|| {
+            let distance = |c: &[u8; 4]| -> u32 {
+                c.iter()
+                    .zip(color.iter())
+                    .map(|(&a, &b)| (i32::from(a) - i32::from(b)).pow(2) as u32)
+                    .sum()
+            };
+            colors
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, c)| distance(c))
+                .map(|(i, _)| i as u8)
+                .unwrap_or(0)
+        })
+    }
+}
+
+// Floyd-Steinberg dithering, the error of each channel is carried to the following pixels
+fn dither(nearest: &mut Nearest, rgba: &[u8], width: usize) -> Vec<u8> {
+    let mut data = Vec::with_capacity(rgba.len() / 4);
+    let mut errors = vec![[0i32; 4]; width + 2];
+    let mut next_errors = vec![[0i32; 4]; width + 2];
+
+    for row in rgba.chunks(width * 4) {
+        for (x, pixel) in row.chunks(4).enumerate() {
+            let mut color = [0u8; 4];
+            for c in 0..4 {
+                let v = i32::from(pixel[c]) + errors[x + 1][c] / 16;
+                color[c] = v.clamp(0, 255) as u8;
+            }
+            let index = nearest.index(normalize(&color));
+            data.push(index);
+
+            let chosen = nearest.colors[index as usize];
+            for c in 0..4 {
+                let error = i32::from(color[c]) - i32::from(chosen[c]);
+                errors[x + 2][c] += error * 7;
+                next_errors[x][c] += error * 3;
+                next_errors[x + 1][c] += error * 5;
+                next_errors[x + 2][c] += error;
+            }
+        }
+        std::mem::swap(&mut errors, &mut next_errors);
+        next_errors.iter_mut().for_each(|e| *e = [0; 4]);
+    }
+    data
+}
@@ -0,0 +1,268 @@
+use super::errors::{APNGError, APNGResult};
+use png::{BitDepth, ColorType};
+use std::collections::{BTreeSet, HashMap};
+
+use crate::apng::Config;
+use crate::metadata::{Background, Metadata};
+use crate::palette::Palette;
+use crate::png::{PNGImage, pack_samples};
+
+// Finds the smallest color type and bit depth which keeps every pixel of the frames as it is,
+// and returns the config and the frames converted to them.
+// APNG has one IHDR for the whole animation, so all the frames are analyzed together.
+pub fn reduce_images(config: &Config, images: &[PNGImage]) -> APNGResult<(Config, Vec<PNGImage>)> {
+    use ColorType::*;
+
+    if images.is_empty() {
+        return Err(APNGError::ImagesNotFound);
+    }
+    let frames = images
+        .iter()
+        .map(|image| to_rgba16(config, image))
+        .collect::<APNGResult<Vec<_>>>()?;
+
+    let (mut eight, mut opaque, mut gray) = (true, true, true);
+    // smallest depth of the gray samples
+    let mut gray_depth = 1;
+    let mut colors = BTreeSet::new();
+    for pixel in frames.iter().flatten() {
+        let [r, g, b, a] = *pixel;
+        eight &= pixel.iter().all(|v| v % 257 == 0);
+        opaque &= a == 0xffff;
+        gray &= r == g && g == b;
+        while gray_depth < 16 && r % scale(gray_depth) != 0 {
+            gray_depth *= 2;
+        }
+        if colors.len() <= 256 {
+            colors.insert(*pixel);
+        }
+    }
+
+    let depth = if eight { 8 } else { 16 };
+    let mut choices = vec![];
+    if gray && opaque {
+        choices.push((Grayscale, gray_depth));
+    }
+    if gray {
+        choices.push((GrayscaleAlpha, depth));
+    }
+    if opaque {
+        choices.push((Rgb, depth));
+    }
+    choices.push((Rgba, depth));
+    if eight && colors.len() <= 256 {
+        let index_depth = [1, 2, 4, 8]
+            .into_iter()
+            .find(|d| colors.len() <= 1 << d)
+            .unwrap();
+        choices.push((Indexed, index_depth));
+    }
+
+    let pixels: usize = frames.iter().map(|f| f.len()).sum();
+    let (color, depth) = choices
+        .into_iter()
+        .min_by_key(|&(color, depth)| {
+            let palette_size = match color {
+                Indexed => colors.len() * 4,
+                _ => 0,
+            };
+            (pixels * color.samples() * depth).div_ceil(8) + palette_size
+        })
+        .unwrap();
+
+    // transparent entries first, so that tRNS only holds them
+    let mut entries: Vec<[u16; 4]> = colors.into_iter().collect();
+    entries.sort_by_key(|c| c[3] == 0xffff);
+    let index: HashMap<[u16; 4], u16> = entries
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| (c, i as u16))
+        .collect();
+
+    let s = scale(depth);
+    let images = images
+        .iter()
+        .zip(frames.iter())
+        .map(|(image, frame)| {
+            let samples: Vec<u16> = frame
+                .iter()
+                .flat_map(|&[r, g, b, a]| match color {
+                    Grayscale => vec![r / s],
+                    GrayscaleAlpha => vec![r / s, a / s],
+                    Rgb => vec![r / s, g / s, b / s],
+                    Rgba => vec![r / s, g / s, b / s, a / s],
+                    Indexed => vec![index[&[r, g, b, a]]],
+                })
+                .collect();
+            PNGImage {
+                width: image.width,
+                height: image.height,
+                data: pack_samples(&samples, depth, image.width as usize * color.samples()),
+                color_type: color,
+                bit_depth: BitDepth::from_u8(depth as u8).unwrap(),
+            }
+        })
+        .collect();
+
+    let palette = match color {
+        Indexed => {
+            let rgba: Vec<[u8; 4]> = entries.iter().map(|c| c.map(|v| (v / 257) as u8)).collect();
+            Some(Palette::from_rgba(&rgba))
+        }
+        _ => None,
+    };
+    // bKGD and sBIT are in the samples of the color type
+    let metadata = Metadata {
+        background: config
+            .metadata
+            .background
+            .and_then(|b| background_rgb16(config, b))
+            .and_then(|rgb| reduce_background(rgb, color, depth, &entries)),
+        significant_bits: config
+            .metadata
+            .significant_bits
+            .as_ref()
+            .and_then(|bits| reduce_significant_bits(config.color, bits, color, depth)),
+        ..config.metadata.clone()
+    };
+    let config = Config {
+        color,
+        depth: BitDepth::from_u8(depth as u8).unwrap(),
+        palette,
+        metadata,
+        ..config.clone()
+    };
+    Ok((config, images))
+}
+
+// the background color as 16 bit RGB
+fn background_rgb16(config: &Config, background: Background) -> Option<[u16; 3]> {
+    let s = scale(config.depth as usize);
+    match background {
+        Background::Gray(v) => Some([v.checked_mul(s)?; 3]),
+        Background::Rgb(r, g, b) => Some([r.checked_mul(s)?, g.checked_mul(s)?, b.checked_mul(s)?]),
+        Background::Index(i) => {
+            let [r, g, b, _] = config.palette.as_ref()?.rgba(usize::from(i))?;
+            Some([r, g, b].map(|v| u16::from(v) * 257))
+        }
+    }
+}
+
+// None when the color type and bit depth can't represent the background
+fn reduce_background(
+    [r, g, b]: [u16; 3],
+    color: ColorType,
+    depth: usize,
+    entries: &[[u16; 4]],
+) -> Option<Background> {
+    use ColorType::*;
+
+    let s = scale(depth);
+    let exact = [r, g, b].iter().all(|v| v % s == 0);
+    match color {
+        Grayscale | GrayscaleAlpha if exact && r == g && g == b => Some(Background::Gray(r / s)),
+        Rgb | Rgba if exact => Some(Background::Rgb(r / s, g / s, b / s)),
+        Indexed => entries
+            .iter()
+            .position(|c| c[..3] == [r, g, b])
+            .map(|i| Background::Index(i as u8)),
+        _ => None,
+    }
+}
+
+// the significant bits of each RGBA channel mapped to the samples of the reduced color type
+fn reduce_significant_bits(
+    from: ColorType,
+    bits: &[u8],
+    color: ColorType,
+    depth: usize,
+) -> Option<Vec<u8>> {
+    use ColorType::*;
+
+    let (rgb, alpha) = match (from, bits) {
+        (Grayscale, &[v]) => ([v; 3], None),
+        (GrayscaleAlpha, &[v, a]) => ([v; 3], Some(a)),
+        (Rgb | Indexed, &[r, g, b]) => ([r, g, b], None),
+        (Rgba, &[r, g, b, a]) => ([r, g, b], Some(a)),
+        _ => return None,
+    };
+    let max = match color {
+        Indexed => 8,
+        _ => depth as u8,
+    };
+    let alpha = alpha.unwrap_or(max);
+    let gray = rgb.into_iter().max().unwrap();
+    let [r, g, b] = rgb;
+    let bits = match color {
+        Grayscale => vec![gray],
+        GrayscaleAlpha => vec![gray, alpha],
+        Rgb | Indexed => vec![r, g, b],
+        Rgba => vec![r, g, b, alpha],
+    };
+    if bits.contains(&0) {
+        return None;
+    }
+    Some(bits.into_iter().map(|v| v.min(max)).collect())
+}
+
+// the step between the 16 bit values which the bit depth can represent
+fn scale(depth: usize) -> u16 {
+    (0xffff / ((1u32 << depth) - 1)) as u16
+}
+
+// read every pixel as 16 bit RGBA
+fn to_rgba16(config: &Config, image: &PNGImage) -> APNGResult<Vec<[u16; 4]>> {
+    use ColorType::*;
+
+    if image.color_type != config.color || image.bit_depth != config.depth {
+        return Err(APNGError::WrongColorType(
+            config.color,
+            config.depth,
+            image.color_type,
+            image.bit_depth,
+        ));
+    }
+    let samples = image.color_type.samples();
+    let depth = image.bit_depth as usize;
+    let row_len = (image.width as usize * samples * depth).div_ceil(8);
+    let data_size = row_len * image.height as usize;
+    if image.data.len() != data_size {
+        return Err(APNGError::WrongDataSize(data_size, image.data.len()));
+    }
+
+    let mut pixels = Vec::with_capacity(image.width as usize * image.height as usize);
+    for row in image.data.chunks(row_len) {
+        let raw = |i: usize| -> u16 {
+            match depth {
+                16 => u16::from_be_bytes([row[i * 2], row[i * 2 + 1]]),
+                8 => u16::from(row[i]),
+                _ => {
+                    let bit = i * depth;
+                    u16::from(row[bit / 8] >> (8 - depth - bit % 8)) & ((1 << depth) - 1)
+                }
+            }
+        };
+        let sample = |i: usize| raw(i) * scale(depth);
+        for x in 0..image.width as usize {
+            let i = x * samples;
+            let pixel = match image.color_type {
+                Grayscale => [sample(i), sample(i), sample(i), 0xffff],
+                GrayscaleAlpha => [sample(i), sample(i), sample(i), sample(i + 1)],
+                Rgb => [sample(i), sample(i + 1), sample(i + 2), 0xffff],
+                Rgba => [sample(i), sample(i + 1), sample(i + 2), sample(i + 3)],
+                Indexed => {
+                    let index = usize::from(raw(i));
+                    config
+                        .palette
+                        .as_ref()
+                        .ok_or(APNGError::PaletteNotFound)?
+                        .rgba(index)
+                        .ok_or(APNGError::PaletteIndexOutOfRange(index))?
+                        .map(|v| u16::from(v) * 257)
+                }
+            };
+            pixels.push(pixel);
+        }
+    }
+    Ok(pixels)
+}
@@ -0,0 +1,205 @@
+use super::errors::{APNGError, APNGResult};
+use std::io;
+
+use crate::apng::{BlendOp, Config, DisposeOp, Frame};
+use crate::decoder::Decoder;
+use crate::palette::Palette;
+use crate::png::PNGImage;
+
+// composites decoded frames onto a RGBA canvas like a viewer does
+pub struct Renderer<R: io::Read> {
+    decoder: Decoder<R>,
+    canvas: Vec<u8>,
+    // canvas saved before a frame with ApngDisposeOpPrevious
+    saved: Option<Vec<u8>>,
+    // frame rendered last, its dispose_op is applied before the next frame
+    last_frame: Option<Frame>,
+}
+
+impl<R: io::Read> Renderer<R> {
+    pub fn new(reader: R) -> APNGResult<Self> {
+        Ok(Self::from_decoder(Decoder::new(reader)?))
+    }
+
+    pub fn from_decoder(decoder: Decoder<R>) -> Self {
+        let config = decoder.config();
+        let canvas = vec![0; config.width as usize * config.height as usize * 4];
+        Renderer {
+            decoder,
+            canvas,
+            saved: None,
+            last_frame: None,
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        self.decoder.config()
+    }
+
+    // render the next frame, returns the whole canvas and its delay (numerator, denominator)
+    pub fn next_frame(&mut self) -> APNGResult<Option<(PNGImage, (u16, u16))>> {
+        let (image, frame) = match self.decoder.next_frame()? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let (width, height) = (self.config().width, self.config().height);
+        let first = self.last_frame.is_none();
+
+        if let Some(last) = self.last_frame.take() {
+            match last.dispose_op {
+                Some(DisposeOp::ApngDisposeOpBackground) => self.clear(&last),
+                Some(DisposeOp::ApngDisposeOpPrevious) => {
+                    if let Some(saved) = self.saved.take() {
+                        self.canvas = saved;
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        let mut frame = frame;
+        if frame.dispose_op == Some(DisposeOp::ApngDisposeOpPrevious) {
+            if first {
+                // the first frame is treated as ApngDisposeOpBackground
+                frame.dispose_op = Some(DisposeOp::ApngDisposeOpBackground);
+            } else {
+                self.saved = Some(self.canvas.clone());
+            }
+        }
+
+        self.draw(&image, &frame)?;
+        self.last_frame = Some(frame.clone());
+
+        Ok(Some((
+            PNGImage {
+                width,
+                height,
+                data: self.canvas.clone(),
+                color_type: png::ColorType::Rgba,
+                bit_depth: png::BitDepth::Eight,
+            },
+            (frame.delay_num.unwrap_or(0), frame.delay_den.unwrap_or(0)),
+        )))
+    }
+
+    fn clear(&mut self, frame: &Frame) {
+        let canvas_width = self.config().width as usize;
+        let (x, y, w, h) = region(frame);
+        for row in y..y + h {
+            let start = (row * canvas_width + x) * 4;
+            self.canvas[start..start + w * 4].fill(0);
+        }
+    }
+
+    fn draw(&mut self, image: &PNGImage, frame: &Frame) -> APNGResult<()> {
+        let canvas_width = self.config().width as usize;
+        let rgba = to_rgba8(image, self.config().palette.as_ref())?;
+        let (x, y, w, h) = region(frame);
+        let blend = frame.blend_op.unwrap_or(BlendOp::ApngBlendOpSource);
+
+        for (row, src_row) in rgba.chunks(w * 4).take(h).enumerate() {
+            let start = ((y + row) * canvas_width + x) * 4;
+            let dst_row = &mut self.canvas[start..start + w * 4];
+            match blend {
+                BlendOp::ApngBlendOpSource => dst_row.copy_from_slice(src_row),
+                BlendOp::ApngBlendOpOver => {
+                    for (dst, src) in dst_row.chunks_mut(4).zip(src_row.chunks(4)) {
+                        blend_over(dst, src);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: io::Read> Iterator for Renderer<R> {
+    type Item = APNGResult<(PNGImage, (u16, u16))>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_frame().transpose()
+    }
+}
+
+fn region(frame: &Frame) -> (usize, usize, usize, usize) {
+    (
+        frame.offset_x.unwrap_or(0) as usize,
+        frame.offset_y.unwrap_or(0) as usize,
+        frame.width.unwrap_or(0) as usize,
+        frame.height.unwrap_or(0) as usize,
+    )
+}
+
+// alpha compositing of non-premultiplied RGBA, src over dst
+fn blend_over(dst: &mut [u8], src: &[u8]) {
+    let sa = u32::from(src[3]);
+    if sa == 255 {
+        dst.copy_from_slice(src);
+        return;
+    }
+    if sa == 0 {
+        return;
+    }
+    let da = u32::from(dst[3]) * (255 - sa) / 255;
+    let out_a = sa + da;
+    for i in 0..3 {
+        let c = u32::from(src[i]) * sa + u32::from(dst[i]) * da;
+        dst[i] = ((c + out_a / 2) / out_a) as u8;
+    }
+    dst[3] = out_a as u8;
+}
+
+// convert any PNGImage into 8 bit RGBA samples, indexed colors are looked up in the palette
+fn to_rgba8(image: &PNGImage, palette: Option<&Palette>) -> APNGResult<Vec<u8>> {
+    use png::ColorType::*;
+
+    let samples = image.color_type.samples();
+    let depth = image.bit_depth as usize;
+    let row_len = (image.width as usize * samples * depth).div_ceil(8);
+    let max = (1u32 << depth.min(8)) - 1;
+
+    let mut buf = Vec::with_capacity(image.width as usize * image.height as usize * 4);
+    for row in image.data.chunks(row_len).take(image.height as usize) {
+        let raw_sample = |i: usize| -> u32 {
+            let bit = i * depth;
+            (row[bit / 8] >> (8 - depth - bit % 8)) as u32 & max
+        };
+        let sample = |i: usize| -> u8 {
+            match depth {
+                8 => row[i],
+                16 => row[i * 2], // the most significant byte
+                _ => (raw_sample(i) * 255 / max) as u8,
+            }
+        };
+        for x in 0..image.width as usize {
+            let i = x * samples;
+            match image.color_type {
+                Grayscale => {
+                    let g = sample(i);
+                    buf.extend_from_slice(&[g, g, g, 255]);
+                }
+                GrayscaleAlpha => {
+                    let g = sample(i);
+                    buf.extend_from_slice(&[g, g, g, sample(i + 1)]);
+                }
+                Rgb => buf.extend_from_slice(&[sample(i), sample(i + 1), sample(i + 2), 255]),
+                Rgba => {
+                    buf.extend_from_slice(&[sample(i), sample(i + 1), sample(i + 2), sample(i + 3)])
+                }
+                Indexed => {
+                    let index = if depth == 8 {
+                        usize::from(row[i])
+                    } else {
+                        raw_sample(i) as usize
+                    };
+                    let rgba = palette
+                        .ok_or(APNGError::PaletteNotFound)?
+                        .rgba(index)
+                        .ok_or(APNGError::PaletteIndexOutOfRange(index))?;
+                    buf.extend_from_slice(&rgba);
+                }
+            }
+        }
+    }
+    Ok(buf)
+}
@@ -0,0 +1,131 @@
+use super::errors::APNGResult;
+use std::fs::File;
+use std::io::{self, Cursor, Seek, SeekFrom, Write};
+use std::time::Duration;
+
+use crate::apng::{Config, Encoder, Frame};
+use crate::chunk::{ChunkPosition, ChunkType};
+use crate::metadata::Text;
+use crate::png::PNGImage;
+
+// Encodes an animation of an unknown number of frames to a writer which can't seek, like stdout.
+// acTL comes before the frames, so the file is spooled and written to the writer by finish_encode.
+#[derive(Debug)]
+pub struct StreamingEncoder<W: io::Write> {
+    encoder: Encoder<Spool>,
+    w: W,
+}
+
+impl<W: io::Write> StreamingEncoder<W> {
+    // bytes kept in memory before the spool is moved to a temp file
+    pub const DEFAULT_MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+
+    // num_frames of the config is ignored
+    pub fn new(writer: W, config: Config) -> APNGResult<Self> {
+        Self::with_memory_limit(writer, config, Self::DEFAULT_MEMORY_LIMIT)
+    }
+
+    pub fn with_memory_limit(writer: W, config: Config, memory_limit: usize) -> APNGResult<Self> {
+        let spool = Spool::Memory(Cursor::new(vec![]), memory_limit);
+        Ok(StreamingEncoder {
+            encoder: Encoder::new_with_unknown_frames(spool, config)?,
+            w: writer,
+        })
+    }
+
+    pub fn write_frame(&mut self, image: &PNGImage, frame: Frame) -> APNGResult<()> {
+        self.encoder.write_frame(image, frame)
+    }
+
+    pub fn write_frame_at(
+        &mut self,
+        image: &PNGImage,
+        frame: Frame,
+        pts: Duration,
+    ) -> APNGResult<()> {
+        self.encoder.write_frame_at(image, frame, pts)
+    }
+
+    pub fn write_default_image(&mut self, image: &PNGImage) -> APNGResult<()> {
+        self.encoder.write_default_image(image)
+    }
+
+    pub fn add_text(&mut self, text: &Text) -> APNGResult<()> {
+        self.encoder.add_text(text)
+    }
+
+    pub fn add_chunk(
+        &mut self,
+        chunk_type: ChunkType,
+        data: Vec<u8>,
+        position: ChunkPosition,
+    ) -> APNGResult<()> {
+        self.encoder.add_chunk(chunk_type, data, position)
+    }
+
+    // the end is the time when the last frame written with a timestamp is hidden
+    pub fn finish_encode_at(mut self, end: Duration) -> APNGResult<W> {
+        self.encoder.finish_encode_at(end)?;
+        self.write_spool()
+    }
+
+    // Writes the spooled file with the number of frames written, and returns the writer
+    pub fn finish_encode(mut self) -> APNGResult<W> {
+        self.encoder.finish_encode()?;
+        self.write_spool()
+    }
+
+    fn write_spool(mut self) -> APNGResult<W> {
+        let mut spool = self.encoder.into_writer();
+        spool.seek(SeekFrom::Start(0))?;
+        match spool {
+            Spool::Memory(cursor, _) => self.w.write_all(cursor.get_ref())?,
+            Spool::File(mut file) => {
+                io::copy(&mut file, &mut self.w)?;
+            }
+        }
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+// the encoded file, in memory up to the limit and in a temp file after that
+#[derive(Debug)]
+enum Spool {
+    Memory(Cursor<Vec<u8>>, usize),
+    File(File),
+}
+
+impl Write for Spool {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Spool::Memory(cursor, limit) = self
+            && cursor.get_ref().len() + buf.len() > *limit
+        {
+            // the temp file is removed when it is closed
+            let mut file = tempfile::tempfile()?;
+            file.write_all(cursor.get_ref())?;
+            file.seek(SeekFrom::Start(cursor.position()))?;
+            *self = Spool::File(file);
+        }
+        match self {
+            Spool::Memory(cursor, _) => cursor.write(buf),
+            Spool::File(file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Spool::Memory(cursor, _) => cursor.flush(),
+            Spool::File(file) => file.flush(),
+        }
+    }
+}
+
+impl Seek for Spool {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Spool::Memory(cursor, _) => cursor.seek(pos),
+            Spool::File(file) => file.seek(pos),
+        }
+    }
+}
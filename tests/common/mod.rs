@@ -0,0 +1,57 @@
+// fixtures shared by the integration tests, each test crate uses a part of them
+#![allow(dead_code)]
+
+use apng::{Config, Encoder, Frame, PNGImage, Renderer};
+use std::io::Cursor;
+
+// 8 bit frames of noise, which doesn't compress well
+pub fn frames(n: usize, color_type: png::ColorType) -> Vec<PNGImage> {
+    let (width, height) = (24u32, 16u32);
+    let samples = color_type.samples() as u32;
+    (0..n)
+        .map(|i| {
+            let data = (0..width * height * samples)
+                .map(|p| ((p as usize * 7919 + i * 104729) % 251) as u8)
+                .collect();
+            PNGImage {
+                width,
+                height,
+                data,
+                color_type,
+                bit_depth: png::BitDepth::Eight,
+            }
+        })
+        .collect()
+}
+
+pub fn encode(images: &[PNGImage], config: Config) -> Vec<u8> {
+    let mut out = vec![];
+    let mut encoder = Encoder::new(&mut out, config).unwrap();
+    for image in images {
+        encoder.write_frame(image, Frame::default()).unwrap();
+    }
+    encoder.finish_encode().unwrap();
+    out
+}
+
+// decode every frame with the png crate, which checks the CRC of the chunks
+pub fn decode_with_png(data: &[u8]) -> (u32, Vec<Vec<u8>>) {
+    let mut reader = png::Decoder::new(Cursor::new(data)).read_info().unwrap();
+    let num_frames = reader.info().animation_control().unwrap().num_frames;
+    let mut images = vec![];
+    for _ in 0..num_frames {
+        let mut buf = vec![0; reader.output_buffer_size().unwrap()];
+        let info = reader.next_frame(&mut buf).unwrap();
+        buf.truncate(info.buffer_size());
+        images.push(buf);
+    }
+    (num_frames, images)
+}
+
+// the RGBA canvas after each frame
+pub fn render(data: &[u8]) -> Vec<Vec<u8>> {
+    Renderer::new(data)
+        .unwrap()
+        .map(|r| r.unwrap().0.data)
+        .collect()
+}
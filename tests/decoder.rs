@@ -0,0 +1,225 @@
+use apng::errors::APNGError;
+use apng::{Decoder, Renderer};
+use byteorder::{BigEndian, WriteBytesExt};
+use flate2::Crc;
+use flate2::write::ZlibEncoder;
+use std::io::Write;
+
+fn chunk(c_type: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut buf = vec![];
+    buf.write_u32::<BigEndian>(data.len() as u32).unwrap();
+    buf.extend_from_slice(c_type);
+    buf.extend_from_slice(data);
+    let mut crc = Crc::new();
+    crc.update(c_type);
+    crc.update(data);
+    buf.write_u32::<BigEndian>(crc.sum()).unwrap();
+    buf
+}
+
+fn file(chunks: &[Vec<u8>]) -> Vec<u8> {
+    let mut buf = b"\x89PNG\r\n\x1a\n".to_vec();
+    for c in chunks {
+        buf.extend_from_slice(c);
+    }
+    buf
+}
+
+fn ihdr(width: u32, height: u32, depth: u8, color: u8) -> Vec<u8> {
+    let mut buf = vec![];
+    buf.write_u32::<BigEndian>(width).unwrap();
+    buf.write_u32::<BigEndian>(height).unwrap();
+    buf.extend_from_slice(&[depth, color, 0, 0, 0]);
+    chunk(b"IHDR", &buf)
+}
+
+// zlib stream of the raw rows, each one starts with the filter type 0
+fn image_data(raw: &[u8]) -> Vec<u8> {
+    let mut e = ZlibEncoder::new(vec![], flate2::Compression::default());
+    e.write_all(raw).unwrap();
+    e.finish().unwrap()
+}
+
+#[test]
+fn indexed_sixteen_bit_is_rejected() {
+    let data = file(&[
+        ihdr(2, 1, 16, 3),
+        chunk(b"PLTE", &[0, 0, 0, 255, 255, 255]),
+        chunk(b"IDAT", &image_data(&[0, 0, 0, 0, 1])),
+        chunk(b"IEND", &[]),
+    ]);
+    assert!(matches!(
+        Decoder::new(&data[..]),
+        Err(APNGError::InvalidBitDepth(
+            png::ColorType::Indexed,
+            png::BitDepth::Sixteen
+        ))
+    ));
+    assert!(Renderer::new(&data[..]).is_err());
+}
+
+#[test]
+fn invalid_depth_of_color_type_is_rejected() {
+    for (depth, color) in [(1, 2), (4, 4), (2, 6), (16, 3)] {
+        let data = file(&[ihdr(1, 1, depth, color), chunk(b"IEND", &[])]);
+        assert!(
+            matches!(Decoder::new(&data[..]), Err(APNGError::InvalidBitDepth(..))),
+            "depth {depth} color {color}"
+        );
+    }
+}
+
+fn ac_tl(num_frames: u32) -> Vec<u8> {
+    let mut buf = vec![];
+    buf.write_u32::<BigEndian>(num_frames).unwrap();
+    buf.write_u32::<BigEndian>(0).unwrap();
+    chunk(b"acTL", &buf)
+}
+
+// a frame control of the whole 1x1 canvas
+fn fc_tl(seq_num: u32) -> Vec<u8> {
+    let mut buf = vec![];
+    for v in [seq_num, 1, 1, 0, 0] {
+        buf.write_u32::<BigEndian>(v).unwrap();
+    }
+    buf.extend_from_slice(&[0, 1, 0, 10, 0, 0]);
+    chunk(b"fcTL", &buf)
+}
+
+fn fd_at(seq_num: u32, gray: u8) -> Vec<u8> {
+    let mut buf = vec![];
+    buf.write_u32::<BigEndian>(seq_num).unwrap();
+    buf.extend_from_slice(&image_data(&[0, gray]));
+    chunk(b"fdAT", &buf)
+}
+
+// a 1x1 8 bit gray animation of 2 frames
+fn animation(fc_tl_seq: u32, fd_at_seq: u32) -> Vec<u8> {
+    file(&[
+        ihdr(1, 1, 8, 0),
+        ac_tl(2),
+        fc_tl(0),
+        chunk(b"IDAT", &image_data(&[0, 10])),
+        fc_tl(fc_tl_seq),
+        fd_at(fd_at_seq, 20),
+        chunk(b"IEND", &[]),
+    ])
+}
+
+fn decode(data: &[u8]) -> Result<Vec<u8>, APNGError> {
+    let mut pixels = vec![];
+    for frame in Decoder::new(data)? {
+        pixels.extend_from_slice(&frame?.0.data);
+    }
+    Ok(pixels)
+}
+
+#[test]
+fn sequence_numbers() {
+    assert_eq!(decode(&animation(1, 2)).unwrap(), vec![10, 20]);
+    assert!(matches!(
+        decode(&animation(2, 3)),
+        Err(APNGError::InvalidChunk(c)) if c == "fcTL"
+    ));
+    assert!(matches!(
+        decode(&animation(1, 1)),
+        Err(APNGError::InvalidChunk(c)) if c == "fdAT"
+    ));
+}
+
+#[test]
+fn crc_mismatch() {
+    let mut data = animation(1, 2);
+    // the last byte of the IHDR data
+    data[8 + 8 + 12] ^= 1;
+    assert!(matches!(
+        Decoder::new(&data[..]),
+        Err(APNGError::CrcMismatch(c)) if c == "IHDR"
+    ));
+
+    let mut data = animation(1, 2);
+    let iend = data.len() - 12;
+    // the CRC of the fdAT
+    data[iend - 1] ^= 1;
+    assert!(matches!(decode(&data), Err(APNGError::CrcMismatch(c)) if c == "fdAT"));
+}
+
+#[test]
+fn truncated_chunk() {
+    let data = animation(1, 2);
+    for len in [20, 40, data.len() - 20, data.len() - 1] {
+        assert!(
+            matches!(decode(&data[..len]), Err(APNGError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof),
+            "length {len}"
+        );
+    }
+}
+
+#[test]
+fn fd_at_before_fc_tl() {
+    // IDAT without fcTL is the default image, the fdAT after it has no frame control
+    let data = file(&[
+        ihdr(1, 1, 8, 0),
+        ac_tl(1),
+        chunk(b"IDAT", &image_data(&[0, 10])),
+        fd_at(0, 20),
+        chunk(b"IEND", &[]),
+    ]);
+    assert!(matches!(decode(&data), Err(APNGError::InvalidChunk(c)) if c == "fdAT"));
+}
+
+#[test]
+fn invalid_ancillary_chunks_are_skipped() {
+    let invalid = [
+        // no null separator after the keyword
+        chunk(b"tEXt", b"no separator"),
+        chunk(b"gAMA", &[0, 1]),
+        chunk(b"cHRM", &[0; 31]),
+        chunk(b"bKGD", &[0, 0, 0]),
+        chunk(b"pHYs", &[0; 8]),
+        // month 13
+        chunk(b"tIME", &[7, 232, 13, 1, 0, 0, 0]),
+    ];
+    let mut chunks = vec![ihdr(1, 1, 8, 0), ac_tl(2)];
+    chunks.extend_from_slice(&invalid);
+    chunks.extend([
+        fc_tl(0),
+        chunk(b"IDAT", &image_data(&[0, 10])),
+        fc_tl(1),
+        fd_at(2, 20),
+    ]);
+    chunks.extend_from_slice(&invalid);
+    chunks.push(chunk(b"IEND", &[]));
+    let data = file(&chunks);
+
+    assert_eq!(decode(&data).unwrap(), vec![10, 20]);
+    let decoder = Decoder::new(&data[..]).unwrap();
+    assert_eq!(decoder.config().metadata, Default::default());
+}
+
+#[test]
+fn unknown_critical_chunk_is_rejected() {
+    let data = file(&[
+        ihdr(1, 1, 8, 0),
+        chunk(b"CRIt", &[1, 2, 3]),
+        chunk(b"IDAT", &image_data(&[0, 10])),
+        chunk(b"IEND", &[]),
+    ]);
+    assert!(matches!(decode(&data), Err(APNGError::InvalidChunk(c)) if c == "CRIt"));
+
+    // after the image data
+    let mut chunks = vec![ihdr(1, 1, 8, 0), ac_tl(2), fc_tl(0)];
+    chunks.push(chunk(b"IDAT", &image_data(&[0, 10])));
+    chunks.push(chunk(b"CRIt", &[1, 2, 3]));
+    chunks.extend([fc_tl(1), fd_at(2, 20), chunk(b"IEND", &[])]);
+    assert!(matches!(decode(&file(&chunks)), Err(APNGError::InvalidChunk(c)) if c == "CRIt"));
+
+    // an unknown ancillary chunk is skipped
+    let data = file(&[
+        ihdr(1, 1, 8, 0),
+        chunk(b"anCi", &[1, 2, 3]),
+        chunk(b"IDAT", &image_data(&[0, 10])),
+        chunk(b"IEND", &[]),
+    ]);
+    assert_eq!(decode(&data).unwrap(), vec![10]);
+}
@@ -0,0 +1,165 @@
+use apng::errors::APNGError;
+use apng::{
+    Config, Decoder, Encoder, Frame, PNGImage, Timeline, create_config_with_num_frames,
+    delay_fraction,
+};
+use std::time::Duration;
+
+// the smallest error of any fraction with u16 terms
+fn best_error(seconds: f64) -> f64 {
+    (1..=u16::MAX)
+        .map(|den| {
+            let num = (seconds * f64::from(den)).round().min(f64::from(u16::MAX));
+            (num / f64::from(den) - seconds).abs()
+        })
+        .fold(f64::MAX, f64::min)
+}
+
+#[test]
+fn exact_fractions() {
+    assert_eq!(delay_fraction(0.04), (1, 25, 0.0));
+    assert_eq!(delay_fraction(0.5), (1, 2, 0.0));
+    let (num, den, error) = delay_fraction(1.0 / 29.97);
+    assert_eq!((num, den), (100, 2997));
+    assert!(error.abs() < 1e-15);
+}
+
+#[test]
+fn semiconvergent() {
+    // 355/113 is the last convergent of pi which fits, a semiconvergent after it is closer
+    let pi = std::f64::consts::PI;
+    let (num, den, error) = delay_fraction(pi);
+    assert_eq!((num, den), (65298, 20785));
+    assert!(error.abs() < (355.0 / 113.0 - pi).abs());
+    assert!(error.abs() <= best_error(pi));
+}
+
+#[test]
+fn closest_fraction() {
+    for seconds in [
+        0.123456789,
+        7.77777,
+        1234.56789,
+        1.0 / 59.94,
+        0.0416666,
+        3e-5,
+    ] {
+        let (num, den, error) = delay_fraction(seconds);
+        assert_eq!(f64::from(num) / f64::from(den) - seconds, error);
+        assert!(error.abs() <= best_error(seconds), "{seconds}: {num}/{den}");
+    }
+}
+
+#[test]
+fn out_of_range() {
+    assert_eq!(delay_fraction(70000.0), (u16::MAX, 1, -4465.0));
+    assert_eq!(delay_fraction(f64::INFINITY).0, u16::MAX);
+    assert_eq!(delay_fraction(0.0), (0, 1, 0.0));
+    assert_eq!(delay_fraction(-1.0), (0, 1, 1.0));
+    assert!(delay_fraction(f64::NAN).2.is_nan());
+    // 0 is closer than 1/65535
+    assert_eq!(delay_fraction(1e-6), (0, 1, -1e-6));
+    assert_eq!(delay_fraction(1e-5).0, 1);
+    assert_eq!(delay_fraction(1e-5).1, u16::MAX);
+}
+
+#[test]
+fn frame_constructors() {
+    let frame = Frame::with_delay(Duration::from_millis(40));
+    assert_eq!((frame.delay_num, frame.delay_den), (Some(1), Some(25)));
+    assert_eq!(frame.delay(), Some(Duration::from_millis(40)));
+
+    let (frame, error) = Frame::with_delay_exact(Duration::from_secs_f64(std::f64::consts::PI));
+    assert_eq!(
+        (frame.delay_num, frame.delay_den),
+        (Some(65298), Some(20785))
+    );
+    assert!(error < 0.0 && error > -1e-6);
+
+    let (frame, error) = Frame::from_fps_exact(29.97).unwrap();
+    assert_eq!((frame.delay_num, frame.delay_den), (Some(100), Some(2997)));
+    assert!(error.abs() < 1e-15);
+    assert!(Frame::from_fps(0.0).is_err());
+    assert!(Frame::from_fps(-30.0).is_err());
+    assert!(Frame::from_fps(f64::NAN).is_err());
+}
+
+// 29.97 frames per second with a jitter of a few milliseconds
+fn timestamps(n: u64) -> Vec<Duration> {
+    (0..n)
+        .map(|i| {
+            let jitter = (i * 7919 % 7) as i64 - 3;
+            let nanos = (i * 1_001_000_000_000 / 30000) as i64 + jitter * 1_000_000;
+            Duration::from_nanos((10_000_000_000 + nanos) as u64)
+        })
+        .collect()
+}
+
+#[test]
+fn timeline_does_not_drift() {
+    let pts = timestamps(10001);
+    let mut timeline = Timeline::new(1000);
+    let mut ticks = 0u64;
+    for i in 0..pts.len() - 1 {
+        let frame = timeline.frame(pts[i], pts[i + 1]);
+        assert_eq!(frame.delay_den, Some(1000));
+        ticks += u64::from(frame.delay_num.unwrap());
+        let elapsed = (pts[i + 1] - pts[0]).as_secs_f64() * 1000.0;
+        assert!((ticks as f64 - elapsed).abs() <= 1.0, "frame {i}");
+    }
+}
+
+#[test]
+fn timeline_carries_long_delays() {
+    let mut timeline = Timeline::new(1000);
+    let frame = timeline.frame(Duration::ZERO, Duration::from_secs(100));
+    assert_eq!(frame.delay_num, Some(u16::MAX));
+    let frame = timeline.frame(Duration::from_secs(100), Duration::from_secs(101));
+    assert_eq!(frame.delay_num, Some(35465));
+}
+
+#[test]
+fn write_frame_at_does_not_drift() {
+    let image = PNGImage::from_gray(1, 1, png::BitDepth::Eight, &[0]).unwrap();
+    let pts = timestamps(3001);
+    let config = Config {
+        timescale: 90,
+        ..create_config_with_num_frames(&image, 3000, None).unwrap()
+    };
+    let mut out = vec![];
+    let mut encoder = Encoder::new(&mut out, config).unwrap();
+    for &t in &pts[..3000] {
+        encoder.write_frame_at(&image, Frame::default(), t).unwrap();
+    }
+    encoder.finish_encode_at(pts[3000]).unwrap();
+
+    let mut ticks = 0u64;
+    for (i, frame) in Decoder::new(&out[..]).unwrap().enumerate() {
+        let frame = frame.unwrap().1;
+        assert_eq!(frame.delay_den, Some(90));
+        ticks += u64::from(frame.delay_num.unwrap());
+        let elapsed = (pts[i + 1] - pts[0]).as_secs_f64() * 90.0;
+        assert!((ticks as f64 - elapsed).abs() <= 1.0, "frame {i}");
+    }
+}
+
+#[test]
+fn write_frame_after_write_frame_at() {
+    let image = PNGImage::from_gray(1, 1, png::BitDepth::Eight, &[0]).unwrap();
+    let config = create_config_with_num_frames(&image, 2, None).unwrap();
+    let mut out = vec![];
+    let mut encoder = Encoder::new(&mut out, config).unwrap();
+    encoder
+        .write_frame_at(&image, Frame::default(), Duration::ZERO)
+        .unwrap();
+    assert!(matches!(
+        encoder.write_frame(&image, Frame::default()),
+        Err(APNGError::TimestampNotFound)
+    ));
+    encoder
+        .write_frame_at(&image, Frame::default(), Duration::from_millis(100))
+        .unwrap();
+    encoder
+        .finish_encode_at(Duration::from_millis(200))
+        .unwrap();
+}
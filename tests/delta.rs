@@ -0,0 +1,157 @@
+use apng::errors::APNGError;
+use apng::{BlendOp, Config, Decoder, DeltaMode, Encoder, Frame, PNGImage, Palette, create_config};
+use common::{encode, render};
+
+mod common;
+
+// RGBA frames where two red pixels move over noise, far from each other
+fn rgba_frames() -> Vec<PNGImage> {
+    let (width, height) = (16u32, 12u32);
+    (0..4)
+        .map(|i| {
+            let mut data = vec![];
+            for y in 0..height {
+                for x in 0..width {
+                    let moved = (x, y) == (2 + i, 2) || (x, y) == (13 - i, 9);
+                    let noise = ((x * 7919 + y * 104729) % 251) as u8;
+                    data.extend_from_slice(&match moved {
+                        true => [255, 0, 0, 255],
+                        false => [noise, noise.wrapping_mul(3), 90, 255],
+                    });
+                }
+            }
+            PNGImage {
+                width,
+                height,
+                data,
+                color_type: png::ColorType::Rgba,
+                bit_depth: png::BitDepth::Eight,
+            }
+        })
+        .collect()
+}
+
+fn frame_controls(data: &[u8]) -> Vec<Frame> {
+    Decoder::new(data).unwrap().map(|r| r.unwrap().1).collect()
+}
+
+#[test]
+fn rgba_round_trip() {
+    let images = rgba_frames();
+    let base = create_config(&images, None).unwrap();
+    for delta in [DeltaMode::Off, DeltaMode::Crop, DeltaMode::Transparent] {
+        let out = encode(
+            &images,
+            Config {
+                delta,
+                ..base.clone()
+            },
+        );
+        let canvases = render(&out);
+        assert_eq!(canvases.len(), images.len());
+        for (canvas, image) in canvases.iter().zip(images.iter()) {
+            assert_eq!(canvas, &image.data, "{delta:?}");
+        }
+
+        let frames = frame_controls(&out);
+        let sub_frames = frames[1..]
+            .iter()
+            .filter(|f| f.width < Some(base.width) || f.height < Some(base.height))
+            .count();
+        match delta {
+            DeltaMode::Off => assert_eq!(sub_frames, 0),
+            _ => assert_eq!(sub_frames, images.len() - 1, "{delta:?}"),
+        }
+        // most pixels of the region are unchanged noise, which is smaller made transparent
+        let over = frames
+            .iter()
+            .filter(|f| f.blend_op == Some(BlendOp::ApngBlendOpOver))
+            .count();
+        match delta {
+            DeltaMode::Transparent => assert!(over > 0),
+            _ => assert_eq!(over, 0, "{delta:?}"),
+        }
+    }
+}
+
+#[test]
+fn packed_crop_round_trip() {
+    for depth in [1u8, 2, 4] {
+        let (width, height) = (13u32, 5u32);
+        let max = (1u8 << depth) - 1;
+        let images: Vec<PNGImage> = (0..4u32)
+            .map(|i| {
+                let samples: Vec<u8> = (0..width * height)
+                    .map(|p| {
+                        let (x, y) = (p % width, p / width);
+                        // the changed pixels start at odd columns, so the crop is shifted
+                        match (3 + i * 2..6 + i * 2).contains(&x) && y == 2 {
+                            true => max,
+                            false => ((x + y) % 2) as u8,
+                        }
+                    })
+                    .collect();
+                let depth = png::BitDepth::from_u8(depth).unwrap();
+                PNGImage::from_gray(width, height, depth, &samples).unwrap()
+            })
+            .collect();
+
+        let base = create_config(&images, None).unwrap();
+        let plain = encode(&images, base.clone());
+        let crop = encode(
+            &images,
+            Config {
+                delta: DeltaMode::Crop,
+                ..base.clone()
+            },
+        );
+        assert_eq!(render(&crop), render(&plain), "depth {depth}");
+
+        let frames = frame_controls(&crop);
+        for f in &frames[1..] {
+            assert!(f.width < Some(width), "depth {depth}");
+            assert_eq!(f.offset_y, Some(2));
+        }
+        // decoded sub-frames are the pixels of the input
+        let decoded: Vec<(PNGImage, Frame)> = Decoder::new(&crop[..])
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(decoded[0].0.data, images[0].data);
+    }
+}
+
+#[test]
+fn rejected_frame_is_not_diffed_against() {
+    let palette = Palette::new(vec![[0, 0, 0], [255, 255, 255]]);
+    let indices = |v: [u8; 4]| PNGImage::from_indices(4, 1, png::BitDepth::Eight, &v).unwrap();
+    let config = Config {
+        delta: DeltaMode::Crop,
+        palette: Some(palette),
+        ..create_config(&[indices([0; 4])], None).unwrap()
+    };
+    let config = Config {
+        num_frames: 2,
+        ..config
+    };
+
+    let mut out = vec![];
+    let mut encoder = Encoder::new(&mut out, config).unwrap();
+    encoder
+        .write_frame(&indices([0, 0, 0, 0]), Frame::default())
+        .unwrap();
+    // index 5 is out of the palette, the frame is not written
+    assert!(matches!(
+        encoder.write_frame(&indices([0, 1, 0, 5]), Frame::default()),
+        Err(APNGError::PaletteIndexOutOfRange(5))
+    ));
+    encoder
+        .write_frame(&indices([0, 1, 0, 0]), Frame::default())
+        .unwrap();
+    encoder.finish_encode().unwrap();
+
+    let white = [255, 255, 255, 255];
+    let black = [0, 0, 0, 255];
+    let canvases = render(&out);
+    assert_eq!(canvases[1], [black, white, black, black].concat());
+}
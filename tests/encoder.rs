@@ -0,0 +1,315 @@
+use apng::errors::APNGError;
+use apng::{
+    ChunkPosition, ChunkType, Compression, CompressionStrategy, Config, Encoder, Frame, PNGImage,
+    create_config,
+};
+use common::{decode_with_png, encode, frames};
+use std::io::Cursor;
+
+mod common;
+
+#[test]
+fn extra_frame_is_rejected() {
+    let images = frames(4, png::ColorType::Rgb);
+    let config = Config {
+        num_frames: 3,
+        ..create_config(&images, None).unwrap()
+    };
+    let mut out = vec![];
+    let mut encoder = Encoder::new(&mut out, config).unwrap();
+    for image in &images[..3] {
+        encoder.write_frame(image, Frame::default()).unwrap();
+    }
+    assert!(matches!(
+        encoder.write_frame(&images[3], Frame::default()),
+        Err(APNGError::WrongFrameNums(3, 4))
+    ));
+    encoder.finish_encode().unwrap();
+    assert_eq!(decode_with_png(&out).0, 3);
+}
+
+#[test]
+fn missing_frame_is_rejected() {
+    let images = frames(4, png::ColorType::Rgb);
+    let mut out = vec![];
+    let mut encoder = Encoder::new(&mut out, create_config(&images, None).unwrap()).unwrap();
+    encoder.write_frame(&images[0], Frame::default()).unwrap();
+    assert!(matches!(
+        encoder.finish_encode(),
+        Err(APNGError::WrongFrameNums(4, 1))
+    ));
+}
+
+// a frame of a sub-region of the canvas
+fn sub_frame(width: u32, height: u32, offset_x: u32, offset_y: u32) -> Frame {
+    Frame {
+        width: Some(width),
+        height: Some(height),
+        offset_x: Some(offset_x),
+        offset_y: Some(offset_y),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn invalid_frame_region_is_rejected() {
+    let images = frames(2, png::ColorType::Rgb);
+    let mut out = vec![];
+    let mut encoder = Encoder::new(&mut out, create_config(&images, None).unwrap()).unwrap();
+    let small = PNGImage {
+        width: 4,
+        height: 2,
+        data: vec![0; 4 * 2 * 3],
+        color_type: png::ColorType::Rgb,
+        bit_depth: png::BitDepth::Eight,
+    };
+    let empty = PNGImage {
+        width: 0,
+        height: 2,
+        data: vec![],
+        ..small.clone()
+    };
+
+    assert!(matches!(
+        encoder.write_frame(&small, sub_frame(4, 2, 0, 0)),
+        Err(APNGError::InvalidFirstFrame)
+    ));
+    encoder.write_frame(&images[0], Frame::default()).unwrap();
+
+    assert!(matches!(
+        encoder.write_frame(&small, sub_frame(5, 2, 0, 0)),
+        Err(APNGError::WrongFrameSize(5, 2, 4, 2))
+    ));
+    assert!(matches!(
+        encoder.write_frame(&small, sub_frame(4, 2, 21, 0)),
+        Err(APNGError::FrameOutOfBounds)
+    ));
+    assert!(matches!(
+        encoder.write_frame(&small, sub_frame(4, 2, 0, u32::MAX)),
+        Err(APNGError::FrameOutOfBounds)
+    ));
+    assert!(matches!(
+        encoder.write_frame(&empty, sub_frame(0, 2, 0, 0)),
+        Err(APNGError::FrameOutOfBounds)
+    ));
+    assert!(matches!(
+        encoder.write_frame(&empty, Frame::default()),
+        Err(APNGError::FrameOutOfBounds)
+    ));
+
+    // the rejected frames are not written
+    encoder
+        .write_frame(&small, sub_frame(4, 2, 20, 14))
+        .unwrap();
+    encoder.finish_encode().unwrap();
+    assert_eq!(decode_with_png(&out).0, 2);
+}
+
+#[test]
+fn unknown_frame_count() {
+    let images = frames(5, png::ColorType::Rgb);
+    // num_frames of the config is ignored
+    let config = Config {
+        num_frames: 1,
+        ..create_config(&images, None).unwrap()
+    };
+    for prefix in [0, 3] {
+        // the file may start in the middle of the writer
+        let mut cursor = Cursor::new(vec![0xaa; prefix]);
+        cursor.set_position(prefix as u64);
+        let mut encoder = Encoder::new_with_unknown_frames(&mut cursor, config.clone()).unwrap();
+        for image in &images {
+            encoder.write_frame(image, Frame::default()).unwrap();
+        }
+        encoder.finish_encode().unwrap();
+
+        let out = cursor.into_inner();
+        assert_eq!(out[..prefix], vec![0xaa; prefix]);
+        let (num_frames, decoded) = decode_with_png(&out[prefix..]);
+        assert_eq!(num_frames, images.len() as u32);
+        for (data, image) in decoded.iter().zip(images.iter()) {
+            assert_eq!(data, &image.data);
+        }
+    }
+
+    let mut cursor = Cursor::new(vec![]);
+    let mut encoder = Encoder::new_with_unknown_frames(&mut cursor, config).unwrap();
+    assert!(matches!(
+        encoder.finish_encode(),
+        Err(APNGError::ImagesNotFound)
+    ));
+}
+
+// the type and data of the chunks after the signature
+fn chunks(data: &[u8]) -> Vec<(String, &[u8])> {
+    let mut chunks = vec![];
+    let mut i = 8;
+    while i < data.len() {
+        let len = u32::from_be_bytes(data[i..i + 4].try_into().unwrap()) as usize;
+        let c_type = String::from_utf8_lossy(&data[i + 4..i + 8]).into_owned();
+        chunks.push((c_type, &data[i + 8..i + 8 + len]));
+        i += 12 + len;
+    }
+    chunks
+}
+
+fn chunk_types(data: &[u8]) -> Vec<String> {
+    chunks(data).into_iter().map(|(t, _)| t).collect()
+}
+
+#[test]
+fn split_image_data() {
+    let images = frames(3, png::ColorType::Rgb);
+    let config = Config {
+        max_chunk_size: Some(100),
+        ..create_config(&images, None).unwrap()
+    };
+    let mut out = vec![];
+    let mut encoder = Encoder::new(&mut out, config).unwrap();
+    for image in &images {
+        encoder.write_frame(image, Frame::default()).unwrap();
+    }
+    encoder.finish_encode().unwrap();
+
+    let chunks = chunks(&out);
+    let count = |c_type: &str| chunks.iter().filter(|(t, _)| t == c_type).count();
+    // the noise is not compressed to 100 bytes
+    assert!(count("IDAT") > 1);
+    assert!(count("fdAT") > 2 * 2);
+    let mut seq_nums = vec![];
+    for (c_type, data) in &chunks {
+        match c_type.as_str() {
+            "IDAT" => assert!(data.len() <= 100),
+            "fdAT" => assert!(data.len() <= 4 + 100),
+            "fcTL" => {}
+            _ => continue,
+        }
+        if c_type != "IDAT" {
+            seq_nums.push(u32::from_be_bytes(data[..4].try_into().unwrap()));
+        }
+    }
+    // fcTL and every fdAT have their own sequence number
+    assert_eq!(seq_nums, (0..seq_nums.len() as u32).collect::<Vec<_>>());
+
+    let (num_frames, decoded) = decode_with_png(&out);
+    assert_eq!(num_frames, 3);
+    for (data, image) in decoded.iter().zip(images.iter()) {
+        assert_eq!(data, &image.data);
+    }
+}
+
+#[test]
+fn custom_chunk_positions() {
+    let images = frames(4, png::ColorType::Rgb);
+    for default_image in [false, true] {
+        let config = create_config(&images[1..], None).unwrap();
+        let mut out = vec![];
+        let mut encoder = Encoder::new(&mut out, config).unwrap();
+        let positions = [
+            (*b"beFo", ChunkPosition::BeforeImageData),
+            (*b"afTr", ChunkPosition::AfterImageData),
+            (*b"frMa", ChunkPosition::Frame(0)),
+            (*b"frMc", ChunkPosition::Frame(2)),
+        ];
+        for (c_type, position) in positions {
+            encoder
+                .add_chunk(ChunkType(c_type), vec![1, 2, 3], position)
+                .unwrap();
+        }
+        if default_image {
+            encoder.write_default_image(&images[0]).unwrap();
+        }
+        for image in &images[1..] {
+            encoder.write_frame(image, Frame::default()).unwrap();
+        }
+        encoder.finish_encode().unwrap();
+
+        let expected = match default_image {
+            false => vec![
+                "IHDR", "acTL", "beFo", "fcTL", "frMa", "IDAT", "afTr", "fcTL", "fdAT", "fcTL",
+                "frMc", "fdAT", "IEND",
+            ],
+            true => vec![
+                "IHDR", "acTL", "beFo", "IDAT", "afTr", "fcTL", "frMa", "fdAT", "fcTL", "fdAT",
+                "fcTL", "frMc", "fdAT", "IEND",
+            ],
+        };
+        assert_eq!(chunk_types(&out), expected, "default image {default_image}");
+        decode_with_png(&out);
+    }
+}
+
+#[test]
+fn default_image_is_not_a_frame() {
+    let images = frames(3, png::ColorType::Rgb);
+    let config = create_config(&images[1..], None).unwrap();
+    let mut out = vec![];
+    let mut encoder = Encoder::new(&mut out, config).unwrap();
+    encoder.write_default_image(&images[0]).unwrap();
+    for image in &images[1..] {
+        encoder.write_frame(image, Frame::default()).unwrap();
+    }
+    encoder.finish_encode().unwrap();
+
+    // IDAT has no fcTL before it
+    let types = chunk_types(&out);
+    let idat = types.iter().position(|t| t == "IDAT").unwrap();
+    assert!(!types[..idat].contains(&"fcTL".to_string()));
+
+    // the png crate reads the default image first, then the frames
+    let mut reader = png::Decoder::new(Cursor::new(&out)).read_info().unwrap();
+    assert_eq!(reader.info().animation_control().unwrap().num_frames, 2);
+    let mut buf = vec![0; reader.output_buffer_size().unwrap()];
+    reader.next_frame(&mut buf).unwrap();
+    assert!(reader.info().frame_control().is_none());
+    assert_eq!(buf, images[0].data);
+    for image in &images[1..] {
+        reader.next_frame(&mut buf).unwrap();
+        assert!(reader.info().frame_control().is_some());
+        assert_eq!(buf, image.data);
+    }
+}
+
+#[test]
+fn compression_round_trip() {
+    use CompressionStrategy::*;
+
+    let images = frames(2, png::ColorType::Rgb);
+    for level in 0..=10 {
+        for strategy in [Default, Filtered, HuffmanOnly, Rle, Fixed] {
+            let config = Config {
+                compression: Compression { level, strategy },
+                ..create_config(&images, None).unwrap()
+            };
+            let (_, decoded) = decode_with_png(&encode(&images, config));
+            for (data, image) in decoded.iter().zip(images.iter()) {
+                assert_eq!(data, &image.data, "level {level} {strategy:?}");
+            }
+        }
+    }
+}
+
+#[cfg(feature = "zopfli")]
+#[test]
+fn zopfli_round_trip() {
+    use apng::Deflater;
+    use std::num::NonZeroU64;
+
+    let images = frames(2, png::ColorType::Rgb);
+    let base = create_config(&images, None).unwrap();
+    for deflater in [
+        Deflater::zopfli(),
+        Deflater::Zopfli {
+            iterations: NonZeroU64::new(1).unwrap(),
+        },
+    ] {
+        let config = Config {
+            deflater,
+            ..base.clone()
+        };
+        let (_, decoded) = decode_with_png(&encode(&images, config));
+        for (data, image) in decoded.iter().zip(images.iter()) {
+            assert_eq!(data, &image.data, "{deflater:?}");
+        }
+    }
+}
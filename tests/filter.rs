@@ -0,0 +1,237 @@
+use apng::{AdaptiveFilter, Config, Decoder, Encoder, Frame, PNGImage, Palette, create_config};
+use png::{BitDepth, ColorType, Filter};
+use std::io::Cursor;
+
+const COMBINATIONS: &[(ColorType, &[BitDepth])] = &[
+    (
+        ColorType::Grayscale,
+        &[
+            BitDepth::One,
+            BitDepth::Two,
+            BitDepth::Four,
+            BitDepth::Eight,
+            BitDepth::Sixteen,
+        ],
+    ),
+    (ColorType::Rgb, &[BitDepth::Eight, BitDepth::Sixteen]),
+    (
+        ColorType::GrayscaleAlpha,
+        &[BitDepth::Eight, BitDepth::Sixteen],
+    ),
+    (ColorType::Rgba, &[BitDepth::Eight, BitDepth::Sixteen]),
+    (
+        ColorType::Indexed,
+        &[
+            BitDepth::One,
+            BitDepth::Two,
+            BitDepth::Four,
+            BitDepth::Eight,
+        ],
+    ),
+];
+
+const FILTERS: &[Filter] = &[
+    Filter::NoFilter,
+    Filter::Sub,
+    Filter::Up,
+    Filter::Avg,
+    Filter::Paeth,
+    Filter::Adaptive,
+    Filter::MinEntropy,
+];
+
+// gradients with some noise, so that every filter has something to predict
+fn image(color_type: ColorType, bit_depth: BitDepth) -> PNGImage {
+    let (width, height) = (13, 7);
+    let bits = width as usize * color_type.samples() * bit_depth as usize;
+    let row_len = bits.div_ceil(8);
+    let mut data = vec![0; row_len * height as usize];
+    for (y, row) in data.chunks_mut(row_len).enumerate() {
+        for (x, v) in row.iter_mut().enumerate() {
+            *v = (x * 37 + y * 11) as u8 ^ ((x * y * 7919) % 251) as u8;
+        }
+        // padding bits of the last byte are zero
+        let pad = row_len * 8 - bits;
+        row[row_len - 1] &= 0xffu8.checked_shl(pad as u32).unwrap_or(0);
+    }
+    PNGImage {
+        width,
+        height,
+        data,
+        color_type,
+        bit_depth,
+    }
+}
+
+// every index is used, so the palette has all the entries of the bit depth
+fn palette(image: &PNGImage) -> Option<Palette> {
+    if image.color_type != ColorType::Indexed {
+        return None;
+    }
+    let colors = (0..1 << image.bit_depth as u32)
+        .map(|i: u32| [i as u8, (i * 3) as u8, (i * 7) as u8])
+        .collect();
+    Some(Palette::new(colors))
+}
+
+fn encode(image: &PNGImage, filter: Filter) -> Vec<u8> {
+    encode_with(image, filter, false)
+}
+
+fn encode_with(image: &PNGImage, filter: Filter, interlace: bool) -> Vec<u8> {
+    let config = Config {
+        filter,
+        interlace,
+        palette: palette(image),
+        ..create_config(std::slice::from_ref(image), None).unwrap()
+    };
+    let mut out = vec![];
+    let mut encoder = Encoder::new(&mut out, config).unwrap();
+    encoder.write_frame(image, Frame::default()).unwrap();
+    encoder.finish_encode().unwrap();
+    out
+}
+
+#[test]
+fn filter_round_trip_with_png_decoder() {
+    for (color_type, depths) in COMBINATIONS {
+        for &bit_depth in depths.iter() {
+            let image = image(*color_type, bit_depth);
+            for &filter in FILTERS {
+                let out = encode(&image, filter);
+
+                let mut reader = png::Decoder::new(Cursor::new(&out)).read_info().unwrap();
+                let mut buf = vec![0; reader.output_buffer_size().unwrap()];
+                let info = reader.next_frame(&mut buf).unwrap();
+                assert_eq!(
+                    &buf[..info.buffer_size()],
+                    &image.data[..],
+                    "{color_type:?} {bit_depth:?} {filter:?}"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn filter_round_trip_with_decoder() {
+    for (color_type, depths) in COMBINATIONS {
+        for &bit_depth in depths.iter() {
+            let image = image(*color_type, bit_depth);
+            for &filter in FILTERS {
+                let out = encode(&image, filter);
+
+                let (decoded, _) = Decoder::new(Cursor::new(&out))
+                    .unwrap()
+                    .next_frame()
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(decoded, image, "{color_type:?} {bit_depth:?} {filter:?}");
+            }
+        }
+    }
+}
+
+#[test]
+fn interlace_round_trip() {
+    for (color_type, depths) in COMBINATIONS {
+        for &bit_depth in depths.iter() {
+            let image = image(*color_type, bit_depth);
+            for filter in [Filter::NoFilter, Filter::Paeth, Filter::Adaptive] {
+                let out = encode_with(&image, filter, true);
+
+                let mut reader = png::Decoder::new(Cursor::new(&out)).read_info().unwrap();
+                let mut buf = vec![0; reader.output_buffer_size().unwrap()];
+                let info = reader.next_frame(&mut buf).unwrap();
+                assert_eq!(
+                    &buf[..info.buffer_size()],
+                    &image.data[..],
+                    "{color_type:?} {bit_depth:?} {filter:?}"
+                );
+
+                let (decoded, _) = Decoder::new(Cursor::new(&out))
+                    .unwrap()
+                    .next_frame()
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(decoded, image, "{color_type:?} {bit_depth:?} {filter:?}");
+            }
+        }
+    }
+}
+
+#[test]
+fn packed_pixels_round_trip() {
+    let (width, height) = (11, 3);
+    let len = (width * height) as usize;
+    let bitmap: Vec<bool> = (0..len).map(|i| i % 3 == 0).collect();
+    let gray: Vec<u8> = (0..len).map(|i| (i * 5 % 4) as u8).collect();
+    let indices: Vec<u8> = (0..len).map(|i| (i * 7 % 16) as u8).collect();
+
+    let images = [
+        PNGImage::from_bitmap(width, height, &bitmap).unwrap(),
+        PNGImage::from_gray(width, height, BitDepth::Two, &gray).unwrap(),
+        PNGImage::from_indices(width, height, BitDepth::Four, &indices).unwrap(),
+    ];
+    // 11 pixels of 1 bit are packed into 2 bytes, the rest bits are padding
+    assert_eq!(&images[0].data[..2], &[0b10010010, 0b01000000]);
+
+    for image in images.iter() {
+        let out = encode(image, Filter::Adaptive);
+        let (decoded, _) = Decoder::new(Cursor::new(&out))
+            .unwrap()
+            .next_frame()
+            .unwrap()
+            .unwrap();
+        assert_eq!(&decoded, image);
+    }
+
+    assert!(PNGImage::from_gray(width, height, BitDepth::One, &gray).is_err());
+    assert!(PNGImage::from_indices(width, height, BitDepth::Four, &indices[1..]).is_err());
+    assert!(PNGImage::from_gray(0, 3, BitDepth::Four, &[]).is_err());
+    assert!(PNGImage::from_bitmap(3, 0, &[]).is_err());
+}
+
+#[test]
+fn adaptive_filter_is_smaller() {
+    let (width, height) = (64u32, 64u32);
+    let mut data = vec![];
+    for y in 0..height {
+        for x in 0..width {
+            data.extend_from_slice(&[(x * 4) as u8, (y * 4) as u8, ((x + y) * 2) as u8]);
+        }
+    }
+    let image = PNGImage {
+        width,
+        height,
+        data,
+        color_type: ColorType::Rgb,
+        bit_depth: BitDepth::Eight,
+    };
+    let encode = |filter, adaptive_filter| {
+        let config = Config {
+            filter,
+            adaptive_filter,
+            ..create_config(std::slice::from_ref(&image), None).unwrap()
+        };
+        let mut out = vec![];
+        let mut encoder = Encoder::new(&mut out, config).unwrap();
+        encoder.write_frame(&image, Frame::default()).unwrap();
+        encoder.finish_encode().unwrap();
+        let (decoded, _) = Decoder::new(Cursor::new(&out))
+            .unwrap()
+            .next_frame()
+            .unwrap()
+            .unwrap();
+        assert_eq!(decoded, image, "{filter:?} {adaptive_filter:?}");
+        out.len()
+    };
+
+    let no_filter = encode(Filter::NoFilter, AdaptiveFilter::MinSum);
+    let min_sum = encode(Filter::Adaptive, AdaptiveFilter::MinSum);
+    let brute_force = encode(Filter::Adaptive, AdaptiveFilter::BruteForce);
+    // the rows of a gradient are flat after Sub or Up
+    assert!(min_sum * 10 < no_filter, "{min_sum} {no_filter}");
+    assert!(brute_force <= min_sum, "{brute_force} {min_sum}");
+    assert!(encode(Filter::MinEntropy, AdaptiveFilter::MinSum) * 10 < no_filter);
+}
@@ -0,0 +1,166 @@
+use apng::errors::APNGError;
+use apng::{
+    Background, Config, Decoder, Encoder, Frame, IccProfile, Metadata, PNGImage, PixelsPerUnit,
+    Text, Time, create_config_with_num_frames,
+};
+
+fn image() -> PNGImage {
+    PNGImage {
+        width: 2,
+        height: 2,
+        data: (0..2 * 2 * 3).collect(),
+        color_type: png::ColorType::Rgb,
+        bit_depth: png::BitDepth::Eight,
+    }
+}
+
+fn config(metadata: Metadata) -> Config {
+    Config {
+        metadata,
+        ..create_config_with_num_frames(&image(), 1, None).unwrap()
+    }
+}
+
+fn texts() -> Vec<Text> {
+    vec![
+        Text::Latin1 {
+            keyword: "Title".to_string(),
+            text: "round trip".to_string(),
+        },
+        Text::Compressed {
+            keyword: "Comment".to_string(),
+            text: "compressed ".repeat(20),
+        },
+        Text::Utf8 {
+            keyword: "Author".to_string(),
+            language_tag: "ja".to_string(),
+            translated_keyword: "\u{8457}\u{8005}".to_string(),
+            text: "\u{3042}\u{3044}\u{3046}".to_string(),
+            compressed: false,
+        },
+        Text::Utf8 {
+            keyword: "Description".to_string(),
+            language_tag: String::new(),
+            translated_keyword: String::new(),
+            text: "\u{e9}t\u{e9} ".repeat(20),
+            compressed: true,
+        },
+    ]
+}
+
+fn round_trip(metadata: Metadata) {
+    let mut out = vec![];
+    let mut encoder = Encoder::new(&mut out, config(metadata.clone())).unwrap();
+    encoder.write_frame(&image(), Frame::default()).unwrap();
+    encoder.finish_encode().unwrap();
+
+    let decoder = Decoder::new(&out[..]).unwrap();
+    assert_eq!(decoder.config().metadata, metadata);
+}
+
+#[test]
+fn metadata_round_trip() {
+    let common = Metadata {
+        gamma: Some(png::ScaledFloat::from_scaled(45455)),
+        chromaticities: Some(png::SourceChromaticities::new(
+            (0.3127, 0.329),
+            (0.64, 0.33),
+            (0.3, 0.6),
+            (0.15, 0.06),
+        )),
+        significant_bits: Some(vec![5, 6, 5]),
+        background: Some(Background::Rgb(1, 2, 3)),
+        pixels_per_unit: Some(PixelsPerUnit::from_dpi(300)),
+        time: Some(Time {
+            year: 2024,
+            month: 2,
+            day: 29,
+            hour: 23,
+            minute: 59,
+            second: 60,
+        }),
+        texts: texts(),
+        ..Default::default()
+    };
+    // iCCP and sRGB are exclusive
+    round_trip(Metadata {
+        srgb: Some(png::SrgbRenderingIntent::RelativeColorimetric),
+        ..common.clone()
+    });
+    round_trip(Metadata {
+        icc_profile: Some(IccProfile {
+            name: "Display P3".to_string(),
+            data: (0..=255).collect(),
+        }),
+        ..common
+    });
+}
+
+#[test]
+fn icc_profile_with_srgb_is_rejected() {
+    let metadata = Metadata {
+        srgb: Some(png::SrgbRenderingIntent::Perceptual),
+        icc_profile: Some(IccProfile {
+            name: "profile".to_string(),
+            data: vec![1, 2, 3],
+        }),
+        ..Default::default()
+    };
+    let mut out = vec![];
+    assert!(matches!(
+        Encoder::new(&mut out, config(metadata)),
+        Err(APNGError::InvalidChunk(c)) if c == "iCCP"
+    ));
+}
+
+#[test]
+fn invalid_keyword_is_rejected() {
+    let long = "k".repeat(80);
+    for keyword in [
+        "",
+        " leading",
+        "trailing ",
+        "double  space",
+        &long,
+        "non-Latin-1 \u{3042}",
+        "line\nfeed",
+    ] {
+        let mut out = vec![];
+        let mut encoder = Encoder::new(&mut out, config(Metadata::default())).unwrap();
+        let text = Text::Latin1 {
+            keyword: keyword.to_string(),
+            text: "text".to_string(),
+        };
+        assert!(
+            matches!(encoder.add_text(&text), Err(APNGError::InvalidKeyword(_))),
+            "{keyword:?}"
+        );
+    }
+
+    // 79 bytes and Latin-1 characters are fine
+    let mut out = vec![];
+    let mut encoder = Encoder::new(&mut out, config(Metadata::default())).unwrap();
+    for keyword in ["k".repeat(79), "Gr\u{fc}\u{df}e".to_string()] {
+        let text = Text::Compressed {
+            keyword,
+            text: "text".to_string(),
+        };
+        encoder.add_text(&text).unwrap();
+    }
+    encoder.write_frame(&image(), Frame::default()).unwrap();
+    encoder.finish_encode().unwrap();
+
+    // the keyword of iCCP is checked too
+    let metadata = Metadata {
+        icc_profile: Some(IccProfile {
+            name: " profile".to_string(),
+            data: vec![1, 2, 3],
+        }),
+        ..Default::default()
+    };
+    let mut out = vec![];
+    assert!(matches!(
+        Encoder::new(&mut out, config(metadata)),
+        Err(APNGError::InvalidKeyword(_))
+    ));
+}
@@ -0,0 +1,42 @@
+use apng::errors::APNGError;
+use apng::{Config, Encoder, Frame, PNGImage, Palette, Renderer, create_config};
+
+fn encode(image: &PNGImage, palette: Palette) -> Result<Vec<u8>, APNGError> {
+    let config = Config {
+        palette: Some(palette),
+        ..create_config(std::slice::from_ref(image), None).unwrap()
+    };
+    let mut out = vec![];
+    let mut encoder = Encoder::new(&mut out, config)?;
+    encoder.write_frame(image, Frame::default())?;
+    encoder.finish_encode()?;
+    Ok(out)
+}
+
+fn one_bit(width: u32, rows: &[u8]) -> PNGImage {
+    PNGImage {
+        width,
+        height: rows.len() as u32,
+        data: rows.to_vec(),
+        color_type: png::ColorType::Indexed,
+        bit_depth: png::BitDepth::One,
+    }
+}
+
+#[test]
+fn padding_bits_are_not_indices() {
+    let palette = Palette::new(vec![[10, 20, 30]]);
+    // 3 pixels of index 0, the 5 padding bits are not zero
+    let out = encode(&one_bit(3, &[0b0001_1111, 0b0000_0001]), palette).unwrap();
+    let (canvas, _) = Renderer::new(&out[..]).unwrap().next().unwrap().unwrap();
+    assert_eq!(canvas.data, [10, 20, 30, 255].repeat(6));
+}
+
+#[test]
+fn index_out_of_palette() {
+    let palette = Palette::new(vec![[10, 20, 30]]);
+    assert!(matches!(
+        encode(&one_bit(3, &[0b0010_0000]), palette),
+        Err(APNGError::PaletteIndexOutOfRange(1))
+    ));
+}
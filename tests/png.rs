@@ -0,0 +1,37 @@
+use apng::{Encoder, Frame, PNGImage, create_config, load_dynamic_image};
+use std::io::Cursor;
+
+#[test]
+fn sixteen_bit_dynamic_image_is_big_endian() {
+    let buffer =
+        image::ImageBuffer::<image::Rgb<u16>, _>::from_raw(1, 1, vec![0x1234, 0xabcd, 0x00ff])
+            .unwrap();
+    let image = load_dynamic_image(image::DynamicImage::ImageRgb16(buffer)).unwrap();
+    assert_eq!(image.color_type, png::ColorType::Rgb);
+    assert_eq!(image.bit_depth, png::BitDepth::Sixteen);
+    assert_eq!(image.data, [0x12, 0x34, 0xab, 0xcd, 0x00, 0xff]);
+}
+
+#[test]
+fn samples16_round_trip_with_png_decoder() {
+    let samples: Vec<u16> = (0..3 * 2 * 4u16).map(|i| 0x1234 + i * 0x0a0b).collect();
+    let image = PNGImage::from_samples16(3, 2, png::ColorType::Rgba, &samples).unwrap();
+    let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_be_bytes()).collect();
+    assert_eq!(image.data, bytes);
+
+    let config = create_config(std::slice::from_ref(&image), None).unwrap();
+    let mut out = vec![];
+    let mut encoder = Encoder::new(&mut out, config).unwrap();
+    encoder.write_frame(&image, Frame::default()).unwrap();
+    encoder.finish_encode().unwrap();
+
+    let mut reader = png::Decoder::new(Cursor::new(&out)).read_info().unwrap();
+    let mut buf = vec![0; reader.output_buffer_size().unwrap()];
+    let info = reader.next_frame(&mut buf).unwrap();
+    assert_eq!(info.bit_depth, png::BitDepth::Sixteen);
+    let decoded: Vec<u16> = buf[..info.buffer_size()]
+        .chunks(2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .collect();
+    assert_eq!(decoded, samples);
+}
@@ -0,0 +1,131 @@
+use apng::errors::APNGError;
+use apng::{Config, Encoder, Frame, PNGImage, Quantizer, Renderer, create_config};
+
+fn rgba(width: u32, height: u32, pixel: impl Fn(u32, u32) -> [u8; 4]) -> PNGImage {
+    let data = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .flat_map(|(x, y)| pixel(x, y))
+        .collect();
+    PNGImage {
+        width,
+        height,
+        data,
+        color_type: png::ColorType::Rgba,
+        bit_depth: png::BitDepth::Eight,
+    }
+}
+
+// a gradient of many colors
+fn gradient(i: u32) -> PNGImage {
+    rgba(32, 24, |x, y| {
+        [(x * 8) as u8, (y * 10) as u8, (i * 60) as u8, 255]
+    })
+}
+
+#[test]
+fn palette_size() {
+    let images = [gradient(0), gradient(1)];
+    for max_colors in [2, 16, 256] {
+        let quantizer = Quantizer {
+            max_colors,
+            ..Default::default()
+        };
+        let (indexed, palette) = quantizer.quantize(&images).unwrap();
+        assert!(palette.len() <= max_colors);
+        assert!(palette.len() >= 2);
+        for image in &indexed {
+            assert_eq!(image.color_type, png::ColorType::Indexed);
+            assert!(image.data.iter().all(|&i| usize::from(i) < palette.len()));
+        }
+    }
+}
+
+#[test]
+fn colors_are_kept_when_they_fit() {
+    let colors = [
+        [255, 0, 0, 255],
+        [0, 255, 0, 255],
+        [0, 0, 255, 200],
+        [10, 20, 30, 255],
+        [250, 250, 250, 255],
+    ];
+    let images = [
+        rgba(7, 3, |x, y| colors[((x + y) % 5) as usize]),
+        rgba(7, 3, |x, _| colors[(x % 3) as usize]),
+    ];
+    let (indexed, palette) = Quantizer::default().quantize(&images).unwrap();
+    assert_eq!(palette.len(), colors.len());
+    for (image, original) in indexed.iter().zip(images.iter()) {
+        let pixels: Vec<u8> = image
+            .data
+            .iter()
+            .flat_map(|&i| palette.rgba(usize::from(i)).unwrap())
+            .collect();
+        assert_eq!(pixels, original.data);
+    }
+}
+
+#[test]
+fn transparent_pixels() {
+    // fully transparent pixels of any color are one transparent entry
+    let image = rgba(16, 16, |x, y| match (x + y) % 4 {
+        0 => [x as u8 * 16, y as u8 * 16, 0, 0],
+        _ => [x as u8 * 16, y as u8 * 16, 128, 255],
+    });
+    let quantizer = Quantizer {
+        max_colors: 8,
+        ..Default::default()
+    };
+    let (indexed, palette) = quantizer.quantize(std::slice::from_ref(&image)).unwrap();
+    // transparent entries come first, so that tRNS only holds them
+    assert_eq!(palette.rgba(0).unwrap()[3], 0);
+    assert!(palette.alpha.len() < palette.len());
+    for (index, pixel) in indexed[0].data.iter().zip(image.data.chunks(4)) {
+        let alpha = palette.rgba(usize::from(*index)).unwrap()[3];
+        assert_eq!(alpha == 0, pixel[3] == 0);
+    }
+}
+
+#[test]
+fn dithered_indices_are_valid() {
+    let images = [gradient(0), gradient(2)];
+    let quantizer = Quantizer {
+        max_colors: 4,
+        dither: true,
+    };
+    let (indexed, palette) = quantizer.quantize(&images).unwrap();
+    assert!(palette.len() <= 4);
+    for image in &indexed {
+        assert!(image.data.iter().all(|&i| usize::from(i) < palette.len()));
+    }
+
+    // the encoder checks the indices against the palette too
+    let config = Config {
+        palette: Some(palette),
+        ..create_config(&indexed, None).unwrap()
+    };
+    let mut out = vec![];
+    let mut encoder = Encoder::new(&mut out, config).unwrap();
+    for image in &indexed {
+        encoder.write_frame(image, Frame::default()).unwrap();
+    }
+    encoder.finish_encode().unwrap();
+    assert_eq!(Renderer::new(&out[..]).unwrap().count(), 2);
+}
+
+#[test]
+fn empty_frame_is_rejected() {
+    for (width, height) in [(0, 24), (32, 0)] {
+        for dither in [false, true] {
+            let quantizer = Quantizer {
+                max_colors: 16,
+                dither,
+            };
+            let images = [gradient(0), rgba(width, height, |_, _| [0; 4])];
+            assert!(matches!(
+                quantizer.quantize(&images),
+                Err(APNGError::FrameOutOfBounds)
+            ));
+        }
+    }
+}
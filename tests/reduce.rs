@@ -0,0 +1,145 @@
+use apng::{Background, Config, Metadata, PNGImage, create_config, reduce_images};
+use common::encode;
+use png::{BitDepth, ColorType};
+
+mod common;
+
+fn image(width: u32, height: u32, color: ColorType, depth: BitDepth, data: Vec<u8>) -> PNGImage {
+    PNGImage {
+        width,
+        height,
+        data,
+        color_type: color,
+        bit_depth: depth,
+    }
+}
+
+fn render(images: &[PNGImage], config: Config) -> Vec<Vec<u8>> {
+    common::render(&encode(images, config))
+}
+
+// reduce the frames, check the chosen type and that they render the same as before
+fn reduce(images: &[PNGImage]) -> Config {
+    let config = create_config(images, None).unwrap();
+    let (reduced_config, reduced) = reduce_images(&config, images).unwrap();
+    assert_eq!(
+        render(&reduced, reduced_config.clone()),
+        render(images, config)
+    );
+    reduced_config
+}
+
+// 32x32 pixels from a function of the pixel number
+fn rgba(pixel: impl Fn(usize) -> [u8; 4]) -> PNGImage {
+    let data = (0..32 * 32).flat_map(pixel).collect();
+    image(32, 32, ColorType::Rgba, BitDepth::Eight, data)
+}
+
+#[test]
+fn opaque_rgba_to_rgb() {
+    // more than 256 colors
+    let config = reduce(&[rgba(|p| [p as u8, (p / 4) as u8, (p / 7) as u8, 255])]);
+    assert_eq!(
+        (config.color, config.depth),
+        (ColorType::Rgb, BitDepth::Eight)
+    );
+}
+
+#[test]
+fn gray_to_smallest_depth() {
+    for (depth, step) in [
+        (BitDepth::One, 255),
+        (BitDepth::Two, 85),
+        (BitDepth::Four, 17),
+        (BitDepth::Eight, 1),
+    ] {
+        let levels = 255 / step + 1;
+        let config = reduce(&[rgba(|p| {
+            let v = ((p * 7 % levels) * step) as u8;
+            [v, v, v, 255]
+        })]);
+        assert_eq!((config.color, config.depth), (ColorType::Grayscale, depth));
+    }
+}
+
+#[test]
+fn few_colors_to_indexed() {
+    for (colors, depth) in [
+        (2, BitDepth::One),
+        (3, BitDepth::Two),
+        (16, BitDepth::Four),
+        (200, BitDepth::Eight),
+    ] {
+        let config = reduce(&[rgba(|p| {
+            let c = p % colors;
+            [(c * 37) as u8, (c * 11) as u8, 200, 255]
+        })]);
+        assert_eq!((config.color, config.depth), (ColorType::Indexed, depth));
+        assert_eq!(config.palette.unwrap().len(), colors);
+    }
+}
+
+#[test]
+fn sixteen_bit_to_eight_bit() {
+    // every sample is a multiple of 257, and there are more than 256 colors
+    let samples: Vec<u16> = (0..32 * 32)
+        .flat_map(|p: u16| [p % 256 * 257, p / 4 * 257, 9 * 257])
+        .collect();
+    let image = PNGImage::from_samples16(32, 32, ColorType::Rgb, &samples).unwrap();
+    let config = reduce(&[image]);
+    assert_eq!(
+        (config.color, config.depth),
+        (ColorType::Rgb, BitDepth::Eight)
+    );
+}
+
+#[test]
+fn metadata_in_reduced_color_type() {
+    // RGB of two colors which are not gray
+    let data = (0..32 * 32)
+        .flat_map(|p| [[10, 20, 30], [200, 100, 0]][p % 2])
+        .collect();
+    let two_colors = image(32, 32, ColorType::Rgb, BitDepth::Eight, data);
+    let data = (0..32 * 32)
+        .flat_map(|p| [[0; 3], [255; 3]][p % 2])
+        .collect();
+    let black_white = image(32, 32, ColorType::Rgb, BitDepth::Eight, data);
+
+    for (image, background, reduced_background, reduced_bits) in [
+        (
+            &two_colors,
+            Background::Rgb(200, 100, 0),
+            Some(Background::Index(1)),
+            vec![5, 6, 5],
+        ),
+        // not in the palette
+        (&two_colors, Background::Rgb(7, 7, 7), None, vec![5, 6, 5]),
+        (
+            &black_white,
+            Background::Rgb(255, 255, 255),
+            Some(Background::Gray(1)),
+            vec![1],
+        ),
+        // not a gray of 1 bit
+        (&black_white, Background::Rgb(7, 7, 7), None, vec![1]),
+    ] {
+        let images = std::slice::from_ref(image);
+        let config = Config {
+            metadata: Metadata {
+                background: Some(background),
+                significant_bits: Some(vec![5, 6, 5]),
+                ..Default::default()
+            },
+            ..create_config(images, None).unwrap()
+        };
+        let (reduced_config, reduced) = reduce_images(&config, images).unwrap();
+        let metadata = &reduced_config.metadata;
+        assert_eq!(metadata.background, reduced_background, "{background:?}");
+        assert_eq!(metadata.significant_bits, Some(reduced_bits));
+        // the converted chunks are valid for the reduced config
+        assert_eq!(
+            render(&reduced, reduced_config.clone()),
+            render(images, config)
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use apng::{BlendOp, DisposeOp, Encoder, Frame, PNGImage, Renderer, create_config_with_num_frames};
+
+use BlendOp::*;
+use DisposeOp::*;
+
+const R: [u8; 4] = [255, 0, 0, 255];
+const G: [u8; 4] = [0, 255, 0, 255];
+const B: [u8; 4] = [0, 0, 255, 255];
+const W: [u8; 4] = [255, 255, 255, 255];
+const CLEAR: [u8; 4] = [0, 0, 0, 0];
+
+// a row of RGBA pixels
+fn row(pixels: &[[u8; 4]]) -> PNGImage {
+    PNGImage {
+        width: pixels.len() as u32,
+        height: 1,
+        data: pixels.concat(),
+        color_type: png::ColorType::Rgba,
+        bit_depth: png::BitDepth::Eight,
+    }
+}
+
+fn frame(x: u32, width: u32, dispose_op: DisposeOp, blend_op: BlendOp) -> Frame {
+    Frame {
+        width: Some(width),
+        height: Some(1),
+        offset_x: Some(x),
+        offset_y: Some(0),
+        dispose_op: Some(dispose_op),
+        blend_op: Some(blend_op),
+        ..Default::default()
+    }
+}
+
+// encode the frames and render every canvas
+fn render(frames: &[(PNGImage, Frame)]) -> Vec<Vec<[u8; 4]>> {
+    let config = create_config_with_num_frames(&frames[0].0, frames.len() as u32, None).unwrap();
+    let mut out = vec![];
+    let mut encoder = Encoder::new(&mut out, config).unwrap();
+    for (image, frame) in frames {
+        encoder.write_frame(image, frame.clone()).unwrap();
+    }
+    encoder.finish_encode().unwrap();
+
+    Renderer::new(&out[..])
+        .unwrap()
+        .map(|r| {
+            let (canvas, _) = r.unwrap();
+            canvas
+                .data
+                .chunks(4)
+                .map(|p| [p[0], p[1], p[2], p[3]])
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn dispose_background() {
+    let canvases = render(&[
+        (
+            row(&[R, G]),
+            frame(0, 2, ApngDisposeOpNone, ApngBlendOpSource),
+        ),
+        (
+            row(&[B]),
+            frame(0, 1, ApngDisposeOpBackground, ApngBlendOpSource),
+        ),
+        (row(&[W]), frame(1, 1, ApngDisposeOpNone, ApngBlendOpSource)),
+    ]);
+    // only the region of the disposed frame is cleared
+    assert_eq!(canvases, vec![vec![R, G], vec![B, G], vec![CLEAR, W]]);
+}
+
+#[test]
+fn dispose_previous() {
+    let canvases = render(&[
+        (
+            row(&[R, G]),
+            frame(0, 2, ApngDisposeOpNone, ApngBlendOpSource),
+        ),
+        (
+            row(&[B]),
+            frame(0, 1, ApngDisposeOpPrevious, ApngBlendOpSource),
+        ),
+        (row(&[W]), frame(1, 1, ApngDisposeOpNone, ApngBlendOpSource)),
+    ]);
+    // the canvas before the disposed frame is restored
+    assert_eq!(canvases, vec![vec![R, G], vec![B, G], vec![R, W]]);
+}
+
+#[test]
+fn dispose_previous_of_first_frame() {
+    let canvases = render(&[
+        (
+            row(&[R, G]),
+            frame(0, 2, ApngDisposeOpPrevious, ApngBlendOpSource),
+        ),
+        (row(&[W]), frame(1, 1, ApngDisposeOpNone, ApngBlendOpSource)),
+    ]);
+    // treated as ApngDisposeOpBackground
+    assert_eq!(canvases, vec![vec![R, G], vec![CLEAR, W]]);
+}
+
+#[test]
+fn blend_over() {
+    let half_green = [0, 255, 0, 128];
+    let canvases = render(&[
+        (
+            row(&[R, CLEAR, B]),
+            frame(0, 3, ApngDisposeOpNone, ApngBlendOpSource),
+        ),
+        (
+            row(&[half_green, half_green, CLEAR]),
+            frame(0, 3, ApngDisposeOpNone, ApngBlendOpOver),
+        ),
+        (
+            row(&[half_green]),
+            frame(2, 1, ApngDisposeOpNone, ApngBlendOpSource),
+        ),
+    ]);
+    assert_eq!(
+        canvases,
+        vec![
+            vec![R, CLEAR, B],
+            // over opaque red: (255 * 127 + 127) / 255, (255 * 128 + 127) / 255
+            // over transparent: the source as it is, a transparent source keeps blue
+            vec![[127, 128, 0, 255], half_green, B],
+            // ApngBlendOpSource replaces the pixel with its alpha
+            vec![[127, 128, 0, 255], half_green, half_green],
+        ]
+    );
+}
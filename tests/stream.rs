@@ -0,0 +1,32 @@
+use apng::{Config, Decoder, Frame, PNGImage, StreamingEncoder, create_config};
+use common::{encode, frames};
+
+mod common;
+
+#[test]
+fn spooled_in_memory_and_temp_file() {
+    let images = frames(5, png::ColorType::Rgba);
+    let config = create_config(&images, None).unwrap();
+    let expected = encode(&images, config.clone());
+
+    // 0 moves the spool to a temp file at the signature, 100 after the header and
+    // 5000 in the middle of the frames, so acTL is rewritten in the temp file
+    for limit in [usize::MAX, 0, 100, 5000] {
+        let config = Config {
+            num_frames: 0,
+            ..config.clone()
+        };
+        let mut encoder = StreamingEncoder::with_memory_limit(vec![], config, limit).unwrap();
+        for image in &images {
+            encoder.write_frame(image, Frame::default()).unwrap();
+        }
+        let out = encoder.finish_encode().unwrap();
+        assert_eq!(out, expected, "limit {limit}");
+
+        let decoded: Vec<PNGImage> = Decoder::new(&out[..])
+            .unwrap()
+            .map(|r| r.unwrap().0)
+            .collect();
+        assert_eq!(decoded, images);
+    }
+}